@@ -0,0 +1,226 @@
+//! Staking yield estimation from state snapshots
+
+use derive_more::Display;
+use solana_program::native_token::LAMPORTS_PER_SOL;
+
+use crate::{calc::MsolPrice, error::CommonError, state::marinade::Marinade};
+
+#[derive(Debug, Display)]
+pub enum YieldError {
+    NotEnoughSnapshots,
+    EpochsNotIncreasing,
+    ZeroPrice,
+    Calculation(CommonError),
+}
+
+impl From<CommonError> for YieldError {
+    fn from(e: CommonError) -> Self {
+        Self::Calculation(e)
+    }
+}
+
+/// Which yield to report
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YieldBasis {
+    /// What mSOL holders get. reward_fee is already taken by minting mSOL to the treasury
+    Net,
+    /// Rewards before the marinade reward_fee
+    Gross,
+}
+
+/// Price change between two consecutive snapshots
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PriceGrowth {
+    pub from_epoch: u64,
+    pub to_epoch: u64,
+    /// lamports redeemable for 1 mSOL (`calc_lamports_from_msol_amount`)
+    pub from_lamports_per_msol: u64,
+    pub to_lamports_per_msol: u64,
+    /// compounded growth rate per single epoch in the interval.
+    /// Taken from the exact `exact_msol_price` fractions, not from the lamports above:
+    /// growth below 1 lamport per mSOL would be rounded away
+    pub epoch_rate: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct YieldEstimate {
+    pub growth: Vec<PriceGrowth>,
+    /// compounded growth rate per epoch over all snapshots
+    pub epoch_rate: f64,
+    pub apr: f64,
+    pub apy: f64,
+}
+
+/// Approximate count of epochs in a year
+pub fn epochs_per_year(slots_per_epoch: u64, seconds_per_slot: f64) -> f64 {
+    365.25 * 24.0 * 3600.0 / (slots_per_epoch as f64 * seconds_per_slot)
+}
+
+/// Estimate APR and APY from `(epoch, state)` snapshots ordered by epoch
+pub fn estimate_yield(
+    snapshots: &[(u64, Marinade)],
+    epochs_per_year: f64,
+    basis: YieldBasis,
+) -> Result<YieldEstimate, YieldError> {
+    if snapshots.len() < 2 {
+        return Err(YieldError::NotEnoughSnapshots);
+    }
+    let growth = snapshots
+        .windows(2)
+        .map(|pair| price_growth(&pair[0], &pair[1], basis))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let ((first_epoch, first), (last_epoch, last)) =
+        (&snapshots[0], &snapshots[snapshots.len() - 1]);
+    let epoch_rate = compounded_epoch_rate(
+        price_ratio(first.exact_msol_price(), last.exact_msol_price())?,
+        last_epoch - first_epoch,
+        fee_factor(last, basis),
+    );
+    Ok(YieldEstimate {
+        growth,
+        epoch_rate,
+        apr: epoch_rate * epochs_per_year,
+        apy: (1.0 + epoch_rate).powf(epochs_per_year) - 1.0,
+    })
+}
+
+fn price_growth(
+    (from_epoch, from): &(u64, Marinade),
+    (to_epoch, to): &(u64, Marinade),
+    basis: YieldBasis,
+) -> Result<PriceGrowth, YieldError> {
+    if to_epoch <= from_epoch {
+        return Err(YieldError::EpochsNotIncreasing);
+    }
+    Ok(PriceGrowth {
+        from_epoch: *from_epoch,
+        to_epoch: *to_epoch,
+        // the same conversion as used for liquid unstake and order unstake
+        from_lamports_per_msol: from.calc_lamports_from_msol_amount(LAMPORTS_PER_SOL)?,
+        to_lamports_per_msol: to.calc_lamports_from_msol_amount(LAMPORTS_PER_SOL)?,
+        epoch_rate: compounded_epoch_rate(
+            price_ratio(from.exact_msol_price(), to.exact_msol_price())?,
+            to_epoch - from_epoch,
+            fee_factor(to, basis),
+        ),
+    })
+}
+
+/// `to / from`
+fn price_ratio(from: MsolPrice, to: MsolPrice) -> Result<f64, YieldError> {
+    if from.numerator == 0 {
+        return Err(YieldError::ZeroPrice);
+    }
+    // prices are u64 fractions so the products fit u128
+    Ok((to.numerator * from.denominator) as f64 / (to.denominator * from.numerator) as f64)
+}
+
+/// Holders get (1 - reward_fee) of the rewards
+fn fee_factor(state: &Marinade, basis: YieldBasis) -> f64 {
+    match basis {
+        YieldBasis::Net => 1.0,
+        YieldBasis::Gross => 1.0 - state.reward_fee.basis_points as f64 / 10_000.0,
+    }
+}
+
+fn compounded_epoch_rate(ratio: f64, epochs: u64, fee_factor: f64) -> f64 {
+    let net = ratio.powf(1.0 / epochs as f64) - 1.0;
+    if fee_factor > 0.0 {
+        net / fee_factor
+    } else {
+        net
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::fee::Fee;
+
+    fn snapshot(
+        epoch: u64,
+        total_virtual_staked_lamports: u64,
+        msol_supply: u64,
+    ) -> (u64, Marinade) {
+        (
+            epoch,
+            Marinade {
+                available_reserve_balance: total_virtual_staked_lamports,
+                msol_supply,
+                reward_fee: Fee::from_basis_points(1_000),
+                ..Marinade::default()
+            },
+        )
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn net_and_gross_yield() {
+        // price 1 -> 1.1 -> 1.21
+        let snapshots = [
+            snapshot(10, 1_000_000_000, 1_000_000_000),
+            snapshot(11, 1_100_000_000, 1_000_000_000),
+            snapshot(12, 1_210_000_000, 1_000_000_000),
+        ];
+        let net = estimate_yield(&snapshots, 2.0, YieldBasis::Net).unwrap();
+        assert_eq!(net.growth.len(), 2);
+        assert_eq!(net.growth[1].from_lamports_per_msol, 1_100_000_000);
+        assert_eq!(net.growth[1].to_lamports_per_msol, 1_210_000_000);
+        assert_close(net.growth[0].epoch_rate, 0.1);
+        assert_close(net.epoch_rate, 0.1);
+        assert_close(net.apr, 0.2);
+        assert_close(net.apy, 0.21);
+
+        // holders got 90% of the rewards
+        let gross = estimate_yield(&snapshots, 2.0, YieldBasis::Gross).unwrap();
+        assert_close(gross.epoch_rate, 0.1 / 0.9);
+        assert_close(gross.apr, 0.2 / 0.9);
+    }
+
+    #[test]
+    fn growth_below_one_lamport_per_sol() {
+        let snapshots = [
+            snapshot(10, 1_000_000_000_000, 1_000_000_000_000),
+            snapshot(11, 1_000_000_000_500, 1_000_000_000_000),
+        ];
+        let estimate = estimate_yield(&snapshots, 1.0, YieldBasis::Net).unwrap();
+        assert_eq!(
+            estimate.growth[0].from_lamports_per_msol,
+            estimate.growth[0].to_lamports_per_msol
+        );
+        assert!((estimate.epoch_rate - 5e-10).abs() < 1e-15);
+    }
+
+    #[test]
+    fn invalid_snapshots() {
+        assert!(matches!(
+            estimate_yield(&[snapshot(10, 1, 1)], 1.0, YieldBasis::Net),
+            Err(YieldError::NotEnoughSnapshots)
+        ));
+        assert!(matches!(
+            estimate_yield(
+                &[snapshot(10, 1, 1), snapshot(10, 2, 1)],
+                1.0,
+                YieldBasis::Net
+            ),
+            Err(YieldError::EpochsNotIncreasing)
+        ));
+        assert!(matches!(
+            estimate_yield(
+                &[snapshot(10, 0, 1), snapshot(11, 2, 1)],
+                1.0,
+                YieldBasis::Net
+            ),
+            Err(YieldError::ZeroPrice)
+        ));
+    }
+}
@@ -0,0 +1,11 @@
+//! Prints the anchor IDL of the marinade program generated from the SDK types
+//!
+//! cargo run -p marinade-sdk --features idl --bin marinade-idl > marinade_finance.json
+
+fn main() {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&marinade_sdk::idl::marinade_idl())
+            .expect("IDL must serialize")
+    );
+}
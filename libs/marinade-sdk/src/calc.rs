@@ -7,32 +7,330 @@ use std::convert::TryFrom;
 /// as value  = shares * share_price where share_price=total_value/total_shares
 /// or shares = amount_value / share_price where share_price=total_value/total_shares
 ///     => shares = amount_value * 1/share_price where 1/share_price=total_shares/total_value
+/// Rounds down. Fails with CalculationFailure if denominator is zero
 pub fn proportional(amount: u64, numerator: u64, denominator: u64) -> Result<u64, CommonError> {
-    if denominator == 0 {
-        return Ok(amount);
-    }
-    u64::try_from((amount as u128) * (numerator as u128) / (denominator as u128))
+    proportional_rounded(amount, numerator, denominator, Rounding::Floor)
+}
+
+/// proportional rounding up. Use it for amounts the user must pay (e.g. shares to burn)
+pub fn proportional_ceil(
+    amount: u64,
+    numerator: u64,
+    denominator: u64,
+) -> Result<u64, CommonError> {
+    proportional_rounded(amount, numerator, denominator, Rounding::Ceil)
+}
+
+pub fn proportional_rounded(
+    amount: u64,
+    numerator: u64,
+    denominator: u64,
+    rounding: Rounding,
+) -> Result<u64, CommonError> {
+    u64::try_from(rounding.div((amount as u128) * (numerator as u128), denominator as u128)?)
         .map_err(|_| CommonError::CalculationFailure)
 }
 
-#[inline] //alias for proportional
 pub fn value_from_shares(
     shares: u64,
     total_value: u64,
     total_shares: u64,
 ) -> Result<u64, CommonError> {
-    proportional(shares, total_value, total_shares)
+    value_from_shares_rounded(shares, total_value, total_shares, Rounding::Floor)
+}
+
+pub fn value_from_shares_ceil(
+    shares: u64,
+    total_value: u64,
+    total_shares: u64,
+) -> Result<u64, CommonError> {
+    value_from_shares_rounded(shares, total_value, total_shares, Rounding::Ceil)
+}
+
+pub fn value_from_shares_rounded(
+    shares: u64,
+    total_value: u64,
+    total_shares: u64,
+    rounding: Rounding,
+) -> Result<u64, CommonError> {
+    if total_shares == 0 {
+        //no shares minted yet. 1:1 as for the first mint
+        Ok(shares)
+    } else {
+        proportional_rounded(shares, total_value, total_shares, rounding)
+    }
 }
 
 pub fn shares_from_value(
     value: u64,
     total_value: u64,
     total_shares: u64,
+) -> Result<u64, CommonError> {
+    shares_from_value_rounded(value, total_value, total_shares, Rounding::Floor)
+}
+
+pub fn shares_from_value_ceil(
+    value: u64,
+    total_value: u64,
+    total_shares: u64,
+) -> Result<u64, CommonError> {
+    shares_from_value_rounded(value, total_value, total_shares, Rounding::Ceil)
+}
+
+pub fn shares_from_value_rounded(
+    value: u64,
+    total_value: u64,
+    total_shares: u64,
+    rounding: Rounding,
 ) -> Result<u64, CommonError> {
     if total_shares == 0 {
         //no shares minted yet / First mint
         Ok(value)
     } else {
-        proportional(value, total_shares, total_value)
+        proportional_rounded(value, total_shares, total_value, rounding)
+    }
+}
+
+/// Direction to round the result of a division
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Floor,
+    Ceil,
+    /// Round half up
+    Nearest,
+}
+
+impl Rounding {
+    pub fn div(self, numerator: u128, denominator: u128) -> Result<u128, CommonError> {
+        if denominator == 0 {
+            return Err(CommonError::CalculationFailure);
+        }
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        let round_up = match self {
+            Rounding::Floor => false,
+            Rounding::Ceil => remainder != 0,
+            Rounding::Nearest => remainder >= denominator - remainder,
+        };
+        Ok(if round_up { quotient + 1 } else { quotient })
+    }
+}
+
+/// Exact mSOL price as a fraction lamports per mSOL
+/// (total_virtual_staked_lamports / msol_supply)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsolPrice {
+    pub numerator: u128,
+    pub denominator: u128,
+}
+
+impl MsolPrice {
+    /// Price before the first mint
+    pub const ONE: Self = Self {
+        numerator: 1,
+        denominator: 1,
+    };
+
+    pub fn new(total_virtual_staked_lamports: u64, msol_supply: u64) -> Self {
+        if msol_supply == 0 {
+            // no shares minted yet. First mint is 1:1 (see shares_from_value)
+            Self::ONE
+        } else {
+            Self {
+                numerator: total_virtual_staked_lamports as u128,
+                denominator: msol_supply as u128,
+            }
+        }
+    }
+
+    /// lamports = msol_amount * price
+    pub fn to_lamports(&self, msol_amount: u64, rounding: Rounding) -> Result<u64, CommonError> {
+        Self::mul_div(msol_amount, self.numerator, self.denominator, rounding)
+    }
+
+    /// msol = lamports / price
+    pub fn to_msol(&self, lamports: u64, rounding: Rounding) -> Result<u64, CommonError> {
+        Self::mul_div(lamports, self.denominator, self.numerator, rounding)
+    }
+
+    /// Price multiplied by `scale`. With `Marinade::PRICE_DENOMINATOR` it gives the `msol_price` field value
+    pub fn scaled(&self, scale: u64, rounding: Rounding) -> Result<u64, CommonError> {
+        self.to_lamports(scale, rounding)
+    }
+
+    fn mul_div(
+        amount: u64,
+        numerator: u128,
+        denominator: u128,
+        rounding: Rounding,
+    ) -> Result<u64, CommonError> {
+        let product = (amount as u128)
+            .checked_mul(numerator)
+            .ok_or(CommonError::CalculationFailure)?;
+        u64::try_from(rounding.div(product, denominator)?)
+            .map_err(|_| CommonError::CalculationFailure)
+    }
+}
+
+/// SOL per mSOL rounded to the nearest digit.
+/// Precision selects the number of decimals (9 by default, 19 max): `format!("{:.4}", price)`
+impl std::fmt::Display for MsolPrice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.denominator == 0 {
+            return write!(f, "NaN");
+        }
+        let decimals = f.precision().unwrap_or(9).min(19);
+        let unit = 10u128.pow(decimals as u32);
+        let mut integer = self.numerator / self.denominator;
+        // remainder < denominator and unit < 2^64 so it can not overflow for u64 based prices
+        let mut fraction = (self.numerator % self.denominator)
+            .checked_mul(unit)
+            .map(|scaled| Rounding::Nearest.div(scaled, self.denominator).unwrap())
+            .ok_or(std::fmt::Error)?;
+        if fraction == unit {
+            integer += 1;
+            fraction = 0;
+        }
+        if decimals == 0 {
+            write!(f, "{}", integer)
+        } else {
+            write!(f, "{}.{:0width$}", integer, fraction, width = decimals)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const MAX_TOTAL: u64 = 1 << 62;
+
+    /// (value, total_value, total_shares) with value <= total_value
+    fn pool() -> impl Strategy<Value = (u64, u64, u64)> {
+        (1..MAX_TOTAL, 1..MAX_TOTAL).prop_flat_map(|(total_value, total_shares)| {
+            (0..=total_value, Just(total_value), Just(total_shares))
+        })
+    }
+
+    #[test]
+    fn zero_denominator_fails() {
+        assert!(matches!(
+            proportional(1, 1, 0),
+            Err(CommonError::CalculationFailure)
+        ));
+        assert!(proportional_ceil(1, 1, 0).is_err());
+        assert!(shares_from_value(1, 0, 1).is_err());
+        assert_eq!(shares_from_value(5, 0, 0).unwrap(), 5);
+        assert_eq!(value_from_shares(5, 0, 0).unwrap(), 5);
+    }
+
+    #[test]
+    fn rounding_modes() {
+        assert_eq!(Rounding::Floor.div(7, 2).unwrap(), 3);
+        assert_eq!(Rounding::Ceil.div(7, 2).unwrap(), 4);
+        // half rounds up
+        assert_eq!(Rounding::Nearest.div(7, 2).unwrap(), 4);
+        assert_eq!(Rounding::Nearest.div(10, 3).unwrap(), 3);
+        assert_eq!(Rounding::Nearest.div(11, 3).unwrap(), 4);
+        for rounding in [Rounding::Floor, Rounding::Ceil, Rounding::Nearest] {
+            assert_eq!(rounding.div(6, 3).unwrap(), 2);
+            assert_eq!(rounding.div(0, 3).unwrap(), 0);
+            assert!(rounding.div(1, 0).is_err());
+        }
+    }
+
+    #[test]
+    fn msol_price_conversions() {
+        // 1.5 lamports per mSOL
+        let price = MsolPrice::new(3_000, 2_000);
+        assert_eq!(price.to_lamports(3, Rounding::Floor).unwrap(), 4);
+        assert_eq!(price.to_lamports(3, Rounding::Ceil).unwrap(), 5);
+        assert_eq!(price.to_lamports(3, Rounding::Nearest).unwrap(), 5);
+        assert_eq!(price.to_msol(5, Rounding::Floor).unwrap(), 3);
+        assert_eq!(price.to_msol(5, Rounding::Ceil).unwrap(), 4);
+        assert_eq!(price.to_msol(4, Rounding::Nearest).unwrap(), 3);
+        assert_eq!(price.scaled(1 << 32, Rounding::Floor).unwrap(), 3 << 31);
+        // the same as the integer helpers
+        assert_eq!(
+            price.to_msol(1_000, Rounding::Floor).unwrap(),
+            shares_from_value(1_000, 3_000, 2_000).unwrap()
+        );
+        assert_eq!(
+            price.to_lamports(1_001, Rounding::Ceil).unwrap(),
+            value_from_shares_ceil(1_001, 3_000, 2_000).unwrap()
+        );
+
+        assert_eq!(MsolPrice::new(3_000, 0), MsolPrice::ONE);
+        assert_eq!(MsolPrice::ONE.to_msol(7, Rounding::Floor).unwrap(), 7);
+        assert!(MsolPrice::new(0, 1).to_msol(7, Rounding::Floor).is_err());
+        assert!(MsolPrice::new(u64::MAX, 1)
+            .to_lamports(2, Rounding::Floor)
+            .is_err());
+    }
+
+    #[test]
+    fn msol_price_display() {
+        let price = MsolPrice::new(3_000, 2_000);
+        assert_eq!(price.to_string(), "1.500000000");
+        assert_eq!(format!("{:.2}", price), "1.50");
+        assert_eq!(format!("{:.0}", price), "2");
+        assert_eq!(format!("{:.3}", MsolPrice::new(2, 3)), "0.667");
+        // rounding the fraction up carries into the integer part
+        let almost_two = MsolPrice::new(1_999_999_999_999, 1_000_000_000_000);
+        assert_eq!(almost_two.to_string(), "2.000000000");
+        assert_eq!(format!("{:.12}", almost_two), "1.999999999999");
+        assert_eq!(
+            MsolPrice {
+                numerator: 1,
+                denominator: 0
+            }
+            .to_string(),
+            "NaN"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn ceil_is_floor_or_next(amount in 0..MAX_TOTAL, numerator in 0..MAX_TOTAL, denominator in 1..MAX_TOTAL) {
+            prop_assume!((amount as u128 * numerator as u128 / denominator as u128) < u64::MAX as u128);
+            let floor = proportional(amount, numerator, denominator).unwrap();
+            let ceil = proportional_ceil(amount, numerator, denominator).unwrap();
+            let exact = floor as u128 * denominator as u128 == amount as u128 * numerator as u128;
+            prop_assert_eq!(ceil, if exact { floor } else { floor + 1 });
+        }
+
+        #[test]
+        fn value_shares_value_never_exceeds((value, total_value, total_shares) in pool()) {
+            let shares = shares_from_value(value, total_value, total_shares).unwrap();
+            prop_assert!(value_from_shares(shares, total_value, total_shares).unwrap() <= value);
+            // the same after the deposit is accounted
+            prop_assert!(
+                value_from_shares(shares, total_value + value, total_shares + shares).unwrap() <= value
+            );
+        }
+
+        #[test]
+        fn shares_to_burn_cover_value((value, total_value, total_shares) in pool()) {
+            let burn = shares_from_value_ceil(value, total_value, total_shares).unwrap();
+            prop_assert!(burn <= total_shares);
+            prop_assert!(value_from_shares(burn, total_value, total_shares).unwrap() >= value);
+            prop_assert!(burn >= shares_from_value(value, total_value, total_shares).unwrap());
+        }
+
+        #[test]
+        fn value_ceil_bounds((shares, total_shares, total_value) in pool()) {
+            let floor = value_from_shares(shares, total_value, total_shares).unwrap();
+            let ceil = value_from_shares_ceil(shares, total_value, total_shares).unwrap();
+            prop_assert!(floor <= ceil && ceil <= floor + 1);
+            prop_assert!(ceil <= total_value);
+        }
+
+        #[test]
+        fn first_mint_is_one_to_one(value in any::<u64>(), total_value in any::<u64>()) {
+            prop_assert_eq!(shares_from_value(value, total_value, 0).unwrap(), value);
+            prop_assert_eq!(shares_from_value_ceil(value, total_value, 0).unwrap(), value);
+            prop_assert_eq!(value_from_shares(value, total_value, 0).unwrap(), value);
+        }
     }
 }
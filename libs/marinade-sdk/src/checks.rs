@@ -1,21 +1,219 @@
 use solana_program::stake::state::StakeState;
-use solana_program::{msg, pubkey::Pubkey, entrypoint::ProgramResult, program_error::ProgramError, account_info::AccountInfo};
+use solana_program::{msg, pubkey::Pubkey, program_error::ProgramError, program_option::COption, account_info::AccountInfo};
 use spl_token::state::Mint;
 use spl_token::state::Account as TokenAccount;
 
 use crate::error::CommonError;
 
-pub fn check_min_amount(amount: u64, min_amount: u64, action_name: &str) -> ProgramResult {
+/// Failed check with the values it was evaluated on.
+/// Logged when converted into `ProgramError`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckError {
+    NumberTooLow {
+        action_name: String,
+        amount: u64,
+        min_amount: u64,
+    },
+    Address {
+        field_name: String,
+        expected: Pubkey,
+        actual: Pubkey,
+    },
+    OwnerProgram {
+        field_name: String,
+        expected: Pubkey,
+        actual: Pubkey,
+    },
+    /// owned by neither spl-token nor Token-2022
+    TokenProgram {
+        field_name: String,
+        actual: Pubkey,
+    },
+    Signer {
+        field_name: String,
+        key: Pubkey,
+    },
+    Writable {
+        field_name: String,
+        key: Pubkey,
+    },
+    /// data can not be parsed as the checked account type
+    Unparsable {
+        field_name: String,
+        key: Pubkey,
+        error: String,
+    },
+    MintAuthority {
+        field_name: String,
+        expected: Pubkey,
+        actual: Option<Pubkey>,
+    },
+    FreezeAuthority {
+        field_name: String,
+        actual: Pubkey,
+    },
+    MintNotEmpty {
+        field_name: String,
+        supply: u64,
+    },
+    TokenMint {
+        field_name: String,
+        expected: Pubkey,
+        actual: Pubkey,
+    },
+    TokenOwner {
+        field_name: String,
+        expected: Pubkey,
+        actual: Pubkey,
+    },
+    StakeValidator {
+        expected: Pubkey,
+        actual: Pubkey,
+    },
+    StakeNotDelegated,
+    StakeNotUpdated {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl CheckError {
+    /// The error returned by the program. Does not log
+    pub fn program_error(&self) -> ProgramError {
+        match self {
+            CheckError::NumberTooLow { .. } => CommonError::NumberTooLow.into(),
+            CheckError::Address { .. }
+            | CheckError::OwnerProgram { .. }
+            | CheckError::Writable { .. }
+            | CheckError::MintNotEmpty { .. } => ProgramError::InvalidArgument,
+            CheckError::TokenProgram { .. } => ProgramError::IncorrectProgramId,
+            CheckError::Signer { .. } => ProgramError::MissingRequiredSignature,
+            CheckError::Unparsable { .. }
+            | CheckError::MintAuthority { .. }
+            | CheckError::FreezeAuthority { .. }
+            | CheckError::TokenMint { .. }
+            | CheckError::TokenOwner { .. } => ProgramError::InvalidAccountData,
+            CheckError::StakeValidator { .. } => ProgramError::InvalidInstructionData,
+            CheckError::StakeNotDelegated => CommonError::StakeNotDelegated.into(),
+            CheckError::StakeNotUpdated { .. } => CommonError::StakeAccountNotUpdatedYet.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for CheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckError::NumberTooLow {
+                action_name,
+                amount,
+                min_amount,
+            } => write!(
+                f,
+                "{}: Number too low {} (min is {})",
+                action_name, amount, min_amount
+            ),
+            CheckError::Address {
+                field_name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid {} address: expected {} got {}",
+                field_name, expected, actual
+            ),
+            CheckError::OwnerProgram {
+                field_name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid {} owner_program: expected {} got {}",
+                field_name, expected, actual
+            ),
+            CheckError::TokenProgram { field_name, actual } => write!(
+                f,
+                "Invalid {} owner_program {}. Expected a token program",
+                field_name, actual
+            ),
+            CheckError::Signer { field_name, key } => write!(f, "{} {} must sign", field_name, key),
+            CheckError::Writable { field_name, key } => {
+                write!(f, "{} {} must be writable", field_name, key)
+            }
+            CheckError::Unparsable {
+                field_name,
+                key,
+                error,
+            } => write!(f, "{} {} can not be parsed ({})", field_name, key, error),
+            CheckError::MintAuthority {
+                field_name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid {} mint authority {}. Expected {}",
+                field_name,
+                actual.unwrap_or_default(),
+                expected
+            ),
+            CheckError::FreezeAuthority { field_name, .. } => {
+                write!(f, "Mint {} must have freeze authority not set", field_name)
+            }
+            CheckError::MintNotEmpty { field_name, supply } => {
+                write!(f, "Non empty mint {} supply: {}", field_name, supply)
+            }
+            CheckError::TokenMint {
+                field_name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid token {} mint {}. Expected {}",
+                field_name, actual, expected
+            ),
+            CheckError::TokenOwner {
+                field_name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid token account {} owner {}. Expected {}",
+                field_name, actual, expected
+            ),
+            CheckError::StakeValidator { expected, .. } => write!(
+                f,
+                "Invalid stake validator index. Need to point into validator {}",
+                expected
+            ),
+            CheckError::StakeNotDelegated => write!(f, "Stake account is not delegated"),
+            CheckError::StakeNotUpdated { expected, actual } => write!(
+                f,
+                "Operation on a stake account not yet updated. expected stake:{}, current:{}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl From<CheckError> for ProgramError {
+    fn from(e: CheckError) -> Self {
+        msg!("{}", e);
+        e.program_error()
+    }
+}
+
+pub fn check_min_amount(
+    amount: u64,
+    min_amount: u64,
+    action_name: &str,
+) -> Result<(), CheckError> {
     if amount >= min_amount {
         Ok(())
     } else {
-        msg!(
-            "{}: Number too low {} (min is {})",
-            action_name,
+        Err(CheckError::NumberTooLow {
+            action_name: action_name.to_string(),
             amount,
             min_amount,
-        );
-        Err(CommonError::NumberTooLow.into())
+        })
     }
 }
 
@@ -23,17 +221,15 @@ pub fn check_address(
     actual_address: &Pubkey,
     reference_address: &Pubkey,
     field_name: &str,
-) -> ProgramResult {
+) -> Result<(), CheckError> {
     if actual_address == reference_address {
         Ok(())
     } else {
-        msg!(
-            "Invalid {} address: expected {} got {}",
-            field_name,
-            reference_address,
-            actual_address
-        );
-        Err(ProgramError::InvalidArgument)
+        Err(CheckError::Address {
+            field_name: field_name.to_string(),
+            expected: *reference_address,
+            actual: *actual_address,
+        })
     }
 }
 
@@ -41,18 +237,38 @@ pub fn check_owner_program<'info>(
     account: &AccountInfo<'info>,
     owner: &Pubkey,
     field_name: &str,
-) -> ProgramResult {
+) -> Result<(), CheckError> {
     let actual_owner = account.owner;
     if actual_owner == owner {
         Ok(())
     } else {
-        msg!(
-            "Invalid {} owner_program: expected {} got {}",
-            field_name,
-            owner,
-            actual_owner
-        );
-        Err(ProgramError::InvalidArgument)
+        Err(CheckError::OwnerProgram {
+            field_name: field_name.to_string(),
+            expected: *owner,
+            actual: *actual_owner,
+        })
+    }
+}
+
+pub fn check_signer(account: &AccountInfo, field_name: &str) -> Result<(), CheckError> {
+    if account.is_signer {
+        Ok(())
+    } else {
+        Err(CheckError::Signer {
+            field_name: field_name.to_string(),
+            key: *account.key,
+        })
+    }
+}
+
+pub fn check_writable(account: &AccountInfo, field_name: &str) -> Result<(), CheckError> {
+    if account.is_writable {
+        Ok(())
+    } else {
+        Err(CheckError::Writable {
+            field_name: field_name.to_string(),
+            key: *account.key,
+        })
     }
 }
 
@@ -60,63 +276,68 @@ pub fn check_mint_authority(
     mint: &Mint,
     mint_authority: Pubkey,
     field_name: &str,
-) -> ProgramResult {
+) -> Result<(), CheckError> {
     if mint.mint_authority.contains(&mint_authority) {
         Ok(())
     } else {
-        msg!(
-            "Invalid {} mint authority {}. Expected {}",
-            field_name,
-            mint.mint_authority.unwrap_or_default(),
-            mint_authority
-        );
-        Err(ProgramError::InvalidAccountData)
+        Err(CheckError::MintAuthority {
+            field_name: field_name.to_string(),
+            expected: mint_authority,
+            actual: mint.mint_authority.into(),
+        })
     }
 }
 
-pub fn check_freeze_authority(mint: &Mint, field_name: &str) -> ProgramResult {
-    if mint.freeze_authority.is_none() {
-        Ok(())
-    } else {
-        msg!("Mint {} must have freeze authority not set", field_name);
-        Err(ProgramError::InvalidAccountData)
+pub fn check_freeze_authority(mint: &Mint, field_name: &str) -> Result<(), CheckError> {
+    match mint.freeze_authority {
+        COption::None => Ok(()),
+        COption::Some(actual) => Err(CheckError::FreezeAuthority {
+            field_name: field_name.to_string(),
+            actual,
+        }),
     }
 }
 
-pub fn check_mint_empty(mint: &Mint, field_name: &str) -> ProgramResult {
+pub fn check_mint_empty(mint: &Mint, field_name: &str) -> Result<(), CheckError> {
     if mint.supply == 0 {
         Ok(())
     } else {
-        msg!("Non empty mint {} supply: {}", field_name, mint.supply);
-        Err(ProgramError::InvalidArgument)
+        Err(CheckError::MintNotEmpty {
+            field_name: field_name.to_string(),
+            supply: mint.supply,
+        })
     }
 }
 
-pub fn check_token_mint(token: &TokenAccount, mint: Pubkey, field_name: &str) -> ProgramResult {
+pub fn check_token_mint(
+    token: &TokenAccount,
+    mint: Pubkey,
+    field_name: &str,
+) -> Result<(), CheckError> {
     if token.mint == mint {
         Ok(())
     } else {
-        msg!(
-            "Invalid token {} mint {}. Expected {}",
-            field_name,
-            token.mint,
-            mint
-        );
-        Err(ProgramError::InvalidAccountData)
+        Err(CheckError::TokenMint {
+            field_name: field_name.to_string(),
+            expected: mint,
+            actual: token.mint,
+        })
     }
 }
 
-pub fn check_token_owner(token: &TokenAccount, owner: &Pubkey, field_name: &str) -> ProgramResult {
+pub fn check_token_owner(
+    token: &TokenAccount,
+    owner: &Pubkey,
+    field_name: &str,
+) -> Result<(), CheckError> {
     if token.owner == *owner {
         Ok(())
     } else {
-        msg!(
-            "Invalid token account {} owner {}. Expected {}",
-            field_name,
-            token.owner,
-            owner
-        );
-        Err(ProgramError::InvalidAccountData)
+        Err(CheckError::TokenOwner {
+            field_name: field_name.to_string(),
+            expected: *owner,
+            actual: token.owner,
+        })
     }
 }
 
@@ -126,27 +347,117 @@ pub fn check_stake_amount_and_validator(
     stake_state: &StakeState,
     expected_stake_amount: u64,
     validator_vote_pubkey: &Pubkey,
-) -> ProgramResult {
+) -> Result<(), CheckError> {
     let currently_staked = if let Some(delegation) = stake_state.delegation() {
         if delegation.voter_pubkey != *validator_vote_pubkey {
-            msg!(
-                "Invalid stake validator index. Need to point into validator {}",
-                validator_vote_pubkey
-            );
-            return Err(ProgramError::InvalidInstructionData);
+            return Err(CheckError::StakeValidator {
+                expected: *validator_vote_pubkey,
+                actual: delegation.voter_pubkey,
+            });
         }
         delegation.stake
     } else {
-        return Err(CommonError::StakeNotDelegated.into());
+        return Err(CheckError::StakeNotDelegated);
     };
     // do not allow to operate on an account where last_update_delegated_lamports != currently_staked
     if currently_staked != expected_stake_amount {
-        msg!(
-            "Operation on a stake account not yet updated. expected stake:{}, current:{}",
-            expected_stake_amount,
-            currently_staked
-        );
-        return Err(CommonError::StakeAccountNotUpdatedYet.into());
+        return Err(CheckError::StakeNotUpdated {
+            expected: expected_stake_amount,
+            actual: currently_staked,
+        });
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_error_mapping() {
+        let key = Pubkey::new_unique();
+        let field_name = "field".to_string();
+        let cases = [
+            (
+                CheckError::NumberTooLow {
+                    action_name: "deposit".to_string(),
+                    amount: 1,
+                    min_amount: 2,
+                },
+                CommonError::NumberTooLow.into(),
+            ),
+            (
+                CheckError::Address {
+                    field_name: field_name.clone(),
+                    expected: key,
+                    actual: Pubkey::default(),
+                },
+                ProgramError::InvalidArgument,
+            ),
+            (
+                CheckError::TokenProgram {
+                    field_name: field_name.clone(),
+                    actual: key,
+                },
+                ProgramError::IncorrectProgramId,
+            ),
+            (
+                CheckError::Signer {
+                    field_name: field_name.clone(),
+                    key,
+                },
+                ProgramError::MissingRequiredSignature,
+            ),
+            (
+                CheckError::Writable {
+                    field_name: field_name.clone(),
+                    key,
+                },
+                ProgramError::InvalidArgument,
+            ),
+            (
+                CheckError::Unparsable {
+                    field_name: field_name.clone(),
+                    key,
+                    error: "too short".to_string(),
+                },
+                ProgramError::InvalidAccountData,
+            ),
+            (
+                CheckError::MintAuthority {
+                    field_name,
+                    expected: key,
+                    actual: None,
+                },
+                ProgramError::InvalidAccountData,
+            ),
+            (
+                CheckError::StakeValidator {
+                    expected: key,
+                    actual: Pubkey::default(),
+                },
+                ProgramError::InvalidInstructionData,
+            ),
+            (
+                CheckError::StakeNotDelegated,
+                CommonError::StakeNotDelegated.into(),
+            ),
+            (
+                CheckError::StakeNotUpdated {
+                    expected: 1,
+                    actual: 2,
+                },
+                CommonError::StakeAccountNotUpdatedYet.into(),
+            ),
+        ];
+        for (check_error, program_error) in cases {
+            assert_eq!(
+                check_error.program_error(),
+                program_error,
+                "{}",
+                check_error
+            );
+            assert_eq!(ProgramError::from(check_error), program_error);
+        }
+    }
+}
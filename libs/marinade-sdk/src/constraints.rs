@@ -0,0 +1,427 @@
+//! Declarative account checks reporting all violations at once
+//!
+//! Runs the `check_*` functions from `checks` but collects their errors instead of
+//! failing on the first one, so they can be used for preflight diagnostics off-chain.
+
+use borsh::BorshDeserialize;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
+    pubkey::Pubkey, stake::state::StakeState,
+};
+
+use crate::{
+    checks::{
+        check_address, check_freeze_authority, check_mint_authority, check_owner_program,
+        check_signer, check_stake_amount_and_validator, check_token_mint, check_token_owner,
+        check_writable, CheckError,
+    },
+    token::{check_token_program, unpack_mint_data, unpack_token_account_data},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    Address(Pubkey),
+    OwnerProgram(Pubkey),
+    Signer,
+    Writable,
+    MintAuthority(Pubkey),
+    NoFreezeAuthority,
+    TokenMint(Pubkey),
+    TokenOwner(Pubkey),
+    /// delegated to the validator with the stake amount recorded in the stake list
+    StakeDelegation {
+        validator_vote: Pubkey,
+        stake_amount: u64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstraintViolation {
+    pub field_name: String,
+    pub key: Pubkey,
+    pub constraint: Constraint,
+    /// The error of the corresponding `check_*` function
+    pub error: CheckError,
+}
+
+/// Builder of constraints for a set of accounts.
+/// Every constraint applies to the last added account
+#[derive(Default)]
+pub struct AccountConstraints<'a, 'info> {
+    accounts: Vec<(&'a str, &'a AccountInfo<'info>, Vec<Constraint>)>,
+}
+
+impl<'a, 'info> AccountConstraints<'a, 'info> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn account(mut self, field_name: &'a str, account: &'a AccountInfo<'info>) -> Self {
+        self.accounts.push((field_name, account, vec![]));
+        self
+    }
+
+    pub fn constraint(mut self, constraint: Constraint) -> Self {
+        self.accounts
+            .last_mut()
+            .expect("Add an account before its constraints")
+            .2
+            .push(constraint);
+        self
+    }
+
+    pub fn address(self, address: Pubkey) -> Self {
+        self.constraint(Constraint::Address(address))
+    }
+
+    pub fn owner_program(self, owner: Pubkey) -> Self {
+        self.constraint(Constraint::OwnerProgram(owner))
+    }
+
+    pub fn signer(self) -> Self {
+        self.constraint(Constraint::Signer)
+    }
+
+    pub fn writable(self) -> Self {
+        self.constraint(Constraint::Writable)
+    }
+
+    pub fn mint_authority(self, mint_authority: Pubkey) -> Self {
+        self.constraint(Constraint::MintAuthority(mint_authority))
+    }
+
+    pub fn no_freeze_authority(self) -> Self {
+        self.constraint(Constraint::NoFreezeAuthority)
+    }
+
+    pub fn token_mint(self, mint: Pubkey) -> Self {
+        self.constraint(Constraint::TokenMint(mint))
+    }
+
+    pub fn token_owner(self, owner: Pubkey) -> Self {
+        self.constraint(Constraint::TokenOwner(owner))
+    }
+
+    pub fn stake_delegation(self, validator_vote: Pubkey, stake_amount: u64) -> Self {
+        self.constraint(Constraint::StakeDelegation {
+            validator_vote,
+            stake_amount,
+        })
+    }
+
+    /// Runs all constraints
+    pub fn violations(&self) -> Vec<ConstraintViolation> {
+        self.accounts
+            .iter()
+            .flat_map(|(field_name, account, constraints)| {
+                constraints.iter().filter_map(move |constraint| {
+                    check_constraint(field_name, account, constraint)
+                        .err()
+                        .map(|error| ConstraintViolation {
+                            field_name: field_name.to_string(),
+                            key: *account.key,
+                            constraint: constraint.clone(),
+                            error,
+                        })
+                })
+            })
+            .collect()
+    }
+
+    /// Logs all violations and fails with the error of the first one
+    pub fn check(&self) -> ProgramResult {
+        let violations = self.violations();
+        for violation in violations.iter() {
+            msg!("{}", violation.error);
+        }
+        match violations.first() {
+            Some(violation) => Err(violation.error.program_error()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn check_constraint(
+    field_name: &str,
+    account: &AccountInfo,
+    constraint: &Constraint,
+) -> Result<(), CheckError> {
+    match constraint {
+        Constraint::Address(address) => check_address(account.key, address, field_name),
+        Constraint::OwnerProgram(owner) => check_owner_program(account, owner, field_name),
+        Constraint::Signer => check_signer(account, field_name),
+        Constraint::Writable => check_writable(account, field_name),
+        Constraint::MintAuthority(mint_authority) => check_mint_authority(
+            &unpack(field_name, account, unpack_mint_data)?,
+            *mint_authority,
+            field_name,
+        ),
+        Constraint::NoFreezeAuthority => {
+            check_freeze_authority(&unpack(field_name, account, unpack_mint_data)?, field_name)
+        }
+        Constraint::TokenMint(mint) => check_token_mint(
+            &unpack(field_name, account, unpack_token_account_data)?,
+            *mint,
+            field_name,
+        ),
+        Constraint::TokenOwner(owner) => check_token_owner(
+            &unpack(field_name, account, unpack_token_account_data)?,
+            owner,
+            field_name,
+        ),
+        Constraint::StakeDelegation {
+            validator_vote,
+            stake_amount,
+        } => {
+            // forged stake data in an account of another program must not pass
+            check_owner_program(account, &solana_program::stake::program::ID, field_name)?;
+            let stake_state = StakeState::deserialize(&mut account.data.borrow().as_ref())
+                .map_err(|e| unparsable(field_name, account, e.to_string()))?;
+            check_stake_amount_and_validator(&stake_state, *stake_amount, validator_vote)
+        }
+    }
+}
+
+/// spl-token or Token-2022 state
+fn unpack<T>(
+    field_name: &str,
+    account: &AccountInfo,
+    unpack_data: fn(&Pubkey, &[u8]) -> Result<T, ProgramError>,
+) -> Result<T, CheckError> {
+    check_token_program(account, field_name)?;
+    unpack_data(account.owner, account.data.borrow().as_ref())
+        .map_err(|e| unparsable(field_name, account, e.to_string()))
+}
+
+fn unparsable(field_name: &str, account: &AccountInfo, error: String) -> CheckError {
+    CheckError::Unparsable {
+        field_name: field_name.to_string(),
+        key: *account.key,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshSerialize;
+    use solana_program::{
+        program_option::COption,
+        program_pack::Pack,
+        stake::state::{Meta, Stake},
+    };
+    use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        is_signer: bool,
+        is_writable: bool,
+    }
+
+    impl TestAccount {
+        fn new(owner: Pubkey, data: Vec<u8>) -> Self {
+            Self {
+                key: Pubkey::new_unique(),
+                owner,
+                lamports: 0,
+                data,
+                is_signer: false,
+                is_writable: false,
+            }
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                self.is_writable,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                false,
+                0,
+            )
+        }
+    }
+
+    fn stake_data(voter: Pubkey, lamports: u64) -> Vec<u8> {
+        let mut stake = Stake::default();
+        stake.delegation.voter_pubkey = voter;
+        stake.delegation.stake = lamports;
+        StakeState::Stake(Meta::default(), stake)
+            .try_to_vec()
+            .unwrap()
+    }
+
+    #[test]
+    fn collects_all_violations() {
+        let program = Pubkey::new_unique();
+        let expected = Pubkey::new_unique();
+        let mut account = TestAccount::new(program, vec![]);
+        let info = account.info();
+        let constraints = AccountConstraints::new()
+            .account("state", &info)
+            .address(expected)
+            .owner_program(program)
+            .signer()
+            .writable();
+        let errors: Vec<CheckError> = constraints
+            .violations()
+            .into_iter()
+            .map(|violation| violation.error)
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                CheckError::Address {
+                    field_name: "state".to_string(),
+                    expected,
+                    actual: *info.key
+                },
+                CheckError::Signer {
+                    field_name: "state".to_string(),
+                    key: *info.key
+                },
+                CheckError::Writable {
+                    field_name: "state".to_string(),
+                    key: *info.key
+                },
+            ]
+        );
+        assert_eq!(constraints.check(), Err(ProgramError::InvalidArgument));
+
+        account.is_signer = true;
+        account.is_writable = true;
+        account.key = expected;
+        let info = account.info();
+        let constraints = AccountConstraints::new()
+            .account("state", &info)
+            .address(expected)
+            .signer()
+            .writable();
+        assert!(constraints.violations().is_empty());
+        assert_eq!(constraints.check(), Ok(()));
+    }
+
+    #[test]
+    fn token_constraints() {
+        let authority = Pubkey::new_unique();
+        let mut mint_data = vec![0; Mint::LEN];
+        Mint {
+            mint_authority: COption::Some(authority),
+            is_initialized: true,
+            freeze_authority: COption::Some(authority),
+            ..Mint::default()
+        }
+        .pack_into_slice(&mut mint_data);
+        let mut mint = TestAccount::new(spl_token::ID, mint_data);
+        let mint_info = mint.info();
+
+        let owner = Pubkey::new_unique();
+        let mut token_data = vec![0; TokenAccount::LEN];
+        TokenAccount {
+            mint: *mint_info.key,
+            owner,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        }
+        .pack_into_slice(&mut token_data);
+        let mut token = TestAccount::new(spl_token::ID, token_data);
+        let token_info = token.info();
+        let mut not_token = TestAccount::new(Pubkey::new_unique(), vec![]);
+        let not_token_info = not_token.info();
+
+        let other = Pubkey::new_unique();
+        let violations = AccountConstraints::new()
+            .account("msol_mint", &mint_info)
+            .mint_authority(authority)
+            .no_freeze_authority()
+            .account("token", &token_info)
+            .token_mint(*mint_info.key)
+            .token_owner(other)
+            .account("not_token", &not_token_info)
+            .token_owner(owner)
+            .violations();
+        let errors: Vec<CheckError> = violations
+            .into_iter()
+            .map(|violation| violation.error)
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                CheckError::FreezeAuthority {
+                    field_name: "msol_mint".to_string(),
+                    actual: authority
+                },
+                CheckError::TokenOwner {
+                    field_name: "token".to_string(),
+                    expected: other,
+                    actual: owner
+                },
+                CheckError::TokenProgram {
+                    field_name: "not_token".to_string(),
+                    actual: not_token.owner
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stake_delegation() {
+        let validator = Pubkey::new_unique();
+        let mut stake = TestAccount::new(
+            solana_program::stake::program::ID,
+            stake_data(validator, 100),
+        );
+        let error = |stake: &mut TestAccount, validator, amount| {
+            let info = stake.info();
+            let violations = AccountConstraints::new()
+                .account("stake", &info)
+                .stake_delegation(validator, amount)
+                .violations();
+            violations.first().map(|violation| violation.error.clone())
+        };
+        assert_eq!(error(&mut stake, validator, 100), None);
+        assert_eq!(
+            error(&mut stake, validator, 90),
+            Some(CheckError::StakeNotUpdated {
+                expected: 90,
+                actual: 100
+            })
+        );
+        let other = Pubkey::new_unique();
+        assert_eq!(
+            error(&mut stake, other, 100),
+            Some(CheckError::StakeValidator {
+                expected: other,
+                actual: validator
+            })
+        );
+
+        stake.data = StakeState::Initialized(Meta::default())
+            .try_to_vec()
+            .unwrap();
+        assert_eq!(
+            error(&mut stake, validator, 100),
+            Some(CheckError::StakeNotDelegated)
+        );
+        stake.data = vec![1];
+        assert!(matches!(
+            error(&mut stake, validator, 100),
+            Some(CheckError::Unparsable { .. })
+        ));
+
+        let mut forged = TestAccount::new(Pubkey::new_unique(), stake_data(validator, 100));
+        assert_eq!(
+            error(&mut forged, validator, 100),
+            Some(CheckError::OwnerProgram {
+                field_name: "stake".to_string(),
+                expected: solana_program::stake::program::ID,
+                actual: forged.owner,
+            })
+        );
+    }
+}
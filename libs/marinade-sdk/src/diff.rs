@@ -0,0 +1,637 @@
+//! Field by field comparison of two reads of the state account
+
+use std::{cmp::Reverse, fmt::Display};
+
+use crate::state::{
+    liq_pool::LiqPool, list::List, marinade::Marinade, stake_system::StakeSystem,
+    validator_system::ValidatorSystem,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Balances and counters moving with regular operation
+    Info,
+    /// Parameters set by admins: fees, caps, limits
+    Warning,
+    /// Authorities, linked accounts, bump seeds and list relocations
+    Critical,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "INFO"),
+            Severity::Warning => write!(f, "WARNING"),
+            Severity::Critical => write!(f, "CRITICAL"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldChange {
+    /// Dotted path of the field, e.g. `liq_pool.lp_max_fee`
+    pub path: String,
+    pub before: String,
+    pub after: String,
+    pub severity: Severity,
+}
+
+impl Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {}: {} -> {}",
+            self.severity, self.path, self.before, self.after
+        )
+    }
+}
+
+/// All changed fields in declaration order
+pub fn diff(before: &Marinade, after: &Marinade) -> Vec<FieldChange> {
+    let mut changes = Changes::default();
+    changes.marinade(before, after);
+    changes.0
+}
+
+/// One change per line, most severe first
+pub fn render(changes: &[FieldChange]) -> String {
+    let mut sorted: Vec<&FieldChange> = changes.iter().collect();
+    sorted.sort_by_key(|change| Reverse(change.severity));
+    sorted
+        .iter()
+        .map(|change| change.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Default)]
+struct Changes(Vec<FieldChange>);
+
+impl Changes {
+    fn field<T: PartialEq + Display>(
+        &mut self,
+        path: &str,
+        before: &T,
+        after: &T,
+        severity: Severity,
+    ) {
+        if before != after {
+            self.0.push(FieldChange {
+                path: path.to_string(),
+                before: before.to_string(),
+                after: after.to_string(),
+                severity,
+            })
+        }
+    }
+
+    fn marinade(&mut self, a: &Marinade, b: &Marinade) {
+        use Severity::*;
+        self.field("msol_mint", &a.msol_mint, &b.msol_mint, Critical);
+        self.field(
+            "admin_authority",
+            &a.admin_authority,
+            &b.admin_authority,
+            Critical,
+        );
+        self.field(
+            "operational_sol_account",
+            &a.operational_sol_account,
+            &b.operational_sol_account,
+            Critical,
+        );
+        self.field(
+            "treasury_msol_account",
+            &a.treasury_msol_account,
+            &b.treasury_msol_account,
+            Critical,
+        );
+        self.field(
+            "reserve_bump_seed",
+            &a.reserve_bump_seed,
+            &b.reserve_bump_seed,
+            Critical,
+        );
+        self.field(
+            "msol_mint_authority_bump_seed",
+            &a.msol_mint_authority_bump_seed,
+            &b.msol_mint_authority_bump_seed,
+            Critical,
+        );
+        self.field(
+            "rent_exempt_for_token_acc",
+            &a.rent_exempt_for_token_acc,
+            &b.rent_exempt_for_token_acc,
+            Warning,
+        );
+        self.field("reward_fee", &a.reward_fee, &b.reward_fee, Warning);
+        self.stake_system("stake_system", &a.stake_system, &b.stake_system);
+        self.validator_system("validator_system", &a.validator_system, &b.validator_system);
+        self.liq_pool("liq_pool", &a.liq_pool, &b.liq_pool);
+        self.field(
+            "available_reserve_balance",
+            &a.available_reserve_balance,
+            &b.available_reserve_balance,
+            Info,
+        );
+        self.field("msol_supply", &a.msol_supply, &b.msol_supply, Info);
+        self.field("msol_price", &a.msol_price, &b.msol_price, Info);
+        self.field(
+            "circulating_ticket_count",
+            &a.circulating_ticket_count,
+            &b.circulating_ticket_count,
+            Info,
+        );
+        self.field(
+            "circulating_ticket_balance",
+            &a.circulating_ticket_balance,
+            &b.circulating_ticket_balance,
+            Info,
+        );
+        self.field(
+            "lent_from_reserve",
+            &a.lent_from_reserve,
+            &b.lent_from_reserve,
+            Info,
+        );
+        self.field("min_deposit", &a.min_deposit, &b.min_deposit, Warning);
+        self.field("min_withdraw", &a.min_withdraw, &b.min_withdraw, Warning);
+        self.field(
+            "staking_sol_cap",
+            &a.staking_sol_cap,
+            &b.staking_sol_cap,
+            Warning,
+        );
+        self.field(
+            "emergency_cooling_down",
+            &a.emergency_cooling_down,
+            &b.emergency_cooling_down,
+            Info,
+        );
+    }
+
+    fn list(&mut self, prefix: &str, a: &List, b: &List) {
+        use Severity::*;
+        self.field(
+            &format!("{}.account", prefix),
+            &a.account,
+            &b.account,
+            Critical,
+        );
+        self.field(
+            &format!("{}.item_size", prefix),
+            &a.item_size,
+            &b.item_size,
+            Critical,
+        );
+        self.field(&format!("{}.count", prefix), &a.count, &b.count, Info);
+        self.field(
+            &format!("{}.new_account", prefix),
+            &a.new_account,
+            &b.new_account,
+            Critical,
+        );
+        self.field(
+            &format!("{}.copied_count", prefix),
+            &a.copied_count,
+            &b.copied_count,
+            Info,
+        );
+    }
+
+    fn stake_system(&mut self, prefix: &str, a: &StakeSystem, b: &StakeSystem) {
+        use Severity::*;
+        self.list(
+            &format!("{}.stake_list", prefix),
+            &a.stake_list,
+            &b.stake_list,
+        );
+        self.field(
+            &format!("{}.delayed_unstake_cooling_down", prefix),
+            &a.delayed_unstake_cooling_down,
+            &b.delayed_unstake_cooling_down,
+            Info,
+        );
+        self.field(
+            &format!("{}.stake_deposit_bump_seed", prefix),
+            &a.stake_deposit_bump_seed,
+            &b.stake_deposit_bump_seed,
+            Critical,
+        );
+        self.field(
+            &format!("{}.stake_withdraw_bump_seed", prefix),
+            &a.stake_withdraw_bump_seed,
+            &b.stake_withdraw_bump_seed,
+            Critical,
+        );
+        self.field(
+            &format!("{}.slots_for_stake_delta", prefix),
+            &a.slots_for_stake_delta,
+            &b.slots_for_stake_delta,
+            Warning,
+        );
+        self.field(
+            &format!("{}.last_stake_delta_epoch", prefix),
+            &a.last_stake_delta_epoch,
+            &b.last_stake_delta_epoch,
+            Info,
+        );
+        self.field(
+            &format!("{}.min_stake", prefix),
+            &a.min_stake,
+            &b.min_stake,
+            Warning,
+        );
+        self.field(
+            &format!("{}.extra_stake_delta_runs", prefix),
+            &a.extra_stake_delta_runs,
+            &b.extra_stake_delta_runs,
+            Warning,
+        );
+    }
+
+    fn validator_system(&mut self, prefix: &str, a: &ValidatorSystem, b: &ValidatorSystem) {
+        use Severity::*;
+        self.list(
+            &format!("{}.validator_list", prefix),
+            &a.validator_list,
+            &b.validator_list,
+        );
+        self.field(
+            &format!("{}.manager_authority", prefix),
+            &a.manager_authority,
+            &b.manager_authority,
+            Critical,
+        );
+        self.field(
+            &format!("{}.total_validator_score", prefix),
+            &a.total_validator_score,
+            &b.total_validator_score,
+            Info,
+        );
+        self.field(
+            &format!("{}.total_active_balance", prefix),
+            &a.total_active_balance,
+            &b.total_active_balance,
+            Info,
+        );
+        self.field(
+            &format!("{}.auto_add_validator_enabled", prefix),
+            &a.auto_add_validator_enabled,
+            &b.auto_add_validator_enabled,
+            Warning,
+        );
+    }
+
+    fn liq_pool(&mut self, prefix: &str, a: &LiqPool, b: &LiqPool) {
+        use Severity::*;
+        self.field(
+            &format!("{}.lp_mint", prefix),
+            &a.lp_mint,
+            &b.lp_mint,
+            Critical,
+        );
+        self.field(
+            &format!("{}.lp_mint_authority_bump_seed", prefix),
+            &a.lp_mint_authority_bump_seed,
+            &b.lp_mint_authority_bump_seed,
+            Critical,
+        );
+        self.field(
+            &format!("{}.sol_leg_bump_seed", prefix),
+            &a.sol_leg_bump_seed,
+            &b.sol_leg_bump_seed,
+            Critical,
+        );
+        self.field(
+            &format!("{}.msol_leg_authority_bump_seed", prefix),
+            &a.msol_leg_authority_bump_seed,
+            &b.msol_leg_authority_bump_seed,
+            Critical,
+        );
+        self.field(
+            &format!("{}.msol_leg", prefix),
+            &a.msol_leg,
+            &b.msol_leg,
+            Critical,
+        );
+        self.field(
+            &format!("{}.lp_liquidity_target", prefix),
+            &a.lp_liquidity_target,
+            &b.lp_liquidity_target,
+            Warning,
+        );
+        self.field(
+            &format!("{}.lp_max_fee", prefix),
+            &a.lp_max_fee,
+            &b.lp_max_fee,
+            Warning,
+        );
+        self.field(
+            &format!("{}.lp_min_fee", prefix),
+            &a.lp_min_fee,
+            &b.lp_min_fee,
+            Warning,
+        );
+        self.field(
+            &format!("{}.treasury_cut", prefix),
+            &a.treasury_cut,
+            &b.treasury_cut,
+            Warning,
+        );
+        self.field(
+            &format!("{}.lp_supply", prefix),
+            &a.lp_supply,
+            &b.lp_supply,
+            Info,
+        );
+        self.field(
+            &format!("{}.lent_from_sol_leg", prefix),
+            &a.lent_from_sol_leg,
+            &b.lent_from_sol_leg,
+            Info,
+        );
+        self.field(
+            &format!("{}.liquidity_sol_cap", prefix),
+            &a.liquidity_sol_cap,
+            &b.liquidity_sol_cap,
+            Warning,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::fee::Fee;
+    use solana_program::pubkey::Pubkey;
+
+    fn key(key: &mut Pubkey) {
+        *key = Pubkey::new_unique();
+    }
+
+    fn fee(fee: &mut Fee) {
+        fee.basis_points += 1;
+    }
+
+    type Mutation = (&'static str, fn(&mut Marinade));
+
+    /// Every leaf field of the state with its path and a change of it
+    fn mutations() -> Vec<Mutation> {
+        // Fails to compile when a field is added: add it to `diff` and to the list below
+        let Marinade {
+            msol_mint: _,
+            admin_authority: _,
+            operational_sol_account: _,
+            treasury_msol_account: _,
+            reserve_bump_seed: _,
+            msol_mint_authority_bump_seed: _,
+            rent_exempt_for_token_acc: _,
+            reward_fee: Fee { basis_points: _ },
+            stake_system:
+                StakeSystem {
+                    stake_list:
+                        List {
+                            account: _,
+                            item_size: _,
+                            count: _,
+                            new_account: _,
+                            copied_count: _,
+                        },
+                    delayed_unstake_cooling_down: _,
+                    stake_deposit_bump_seed: _,
+                    stake_withdraw_bump_seed: _,
+                    slots_for_stake_delta: _,
+                    last_stake_delta_epoch: _,
+                    min_stake: _,
+                    extra_stake_delta_runs: _,
+                },
+            validator_system:
+                ValidatorSystem {
+                    validator_list:
+                        List {
+                            account: _,
+                            item_size: _,
+                            count: _,
+                            new_account: _,
+                            copied_count: _,
+                        },
+                    manager_authority: _,
+                    total_validator_score: _,
+                    total_active_balance: _,
+                    auto_add_validator_enabled: _,
+                },
+            liq_pool:
+                LiqPool {
+                    lp_mint: _,
+                    lp_mint_authority_bump_seed: _,
+                    sol_leg_bump_seed: _,
+                    msol_leg_authority_bump_seed: _,
+                    msol_leg: _,
+                    lp_liquidity_target: _,
+                    lp_max_fee: Fee { basis_points: _ },
+                    lp_min_fee: Fee { basis_points: _ },
+                    treasury_cut: Fee { basis_points: _ },
+                    lp_supply: _,
+                    lent_from_sol_leg: _,
+                    liquidity_sol_cap: _,
+                },
+            available_reserve_balance: _,
+            msol_supply: _,
+            msol_price: _,
+            circulating_ticket_count: _,
+            circulating_ticket_balance: _,
+            lent_from_reserve: _,
+            min_deposit: _,
+            min_withdraw: _,
+            staking_sol_cap: _,
+            emergency_cooling_down: _,
+        } = Marinade::default();
+        vec![
+            ("msol_mint", |s| key(&mut s.msol_mint)),
+            ("admin_authority", |s| key(&mut s.admin_authority)),
+            ("operational_sol_account", |s| {
+                key(&mut s.operational_sol_account)
+            }),
+            ("treasury_msol_account", |s| {
+                key(&mut s.treasury_msol_account)
+            }),
+            ("reserve_bump_seed", |s| s.reserve_bump_seed += 1),
+            ("msol_mint_authority_bump_seed", |s| {
+                s.msol_mint_authority_bump_seed += 1
+            }),
+            ("rent_exempt_for_token_acc", |s| {
+                s.rent_exempt_for_token_acc += 1
+            }),
+            ("reward_fee", |s| fee(&mut s.reward_fee)),
+            ("stake_system.stake_list.account", |s| {
+                key(&mut s.stake_system.stake_list.account)
+            }),
+            ("stake_system.stake_list.item_size", |s| {
+                s.stake_system.stake_list.item_size += 1
+            }),
+            ("stake_system.stake_list.count", |s| {
+                s.stake_system.stake_list.count += 1
+            }),
+            ("stake_system.stake_list.new_account", |s| {
+                key(&mut s.stake_system.stake_list.new_account)
+            }),
+            ("stake_system.stake_list.copied_count", |s| {
+                s.stake_system.stake_list.copied_count += 1
+            }),
+            ("stake_system.delayed_unstake_cooling_down", |s| {
+                s.stake_system.delayed_unstake_cooling_down += 1
+            }),
+            ("stake_system.stake_deposit_bump_seed", |s| {
+                s.stake_system.stake_deposit_bump_seed += 1
+            }),
+            ("stake_system.stake_withdraw_bump_seed", |s| {
+                s.stake_system.stake_withdraw_bump_seed += 1
+            }),
+            ("stake_system.slots_for_stake_delta", |s| {
+                s.stake_system.slots_for_stake_delta += 1
+            }),
+            ("stake_system.last_stake_delta_epoch", |s| {
+                s.stake_system.last_stake_delta_epoch += 1
+            }),
+            ("stake_system.min_stake", |s| s.stake_system.min_stake += 1),
+            ("stake_system.extra_stake_delta_runs", |s| {
+                s.stake_system.extra_stake_delta_runs += 1
+            }),
+            ("validator_system.validator_list.account", |s| {
+                key(&mut s.validator_system.validator_list.account)
+            }),
+            ("validator_system.validator_list.item_size", |s| {
+                s.validator_system.validator_list.item_size += 1
+            }),
+            ("validator_system.validator_list.count", |s| {
+                s.validator_system.validator_list.count += 1
+            }),
+            ("validator_system.validator_list.new_account", |s| {
+                key(&mut s.validator_system.validator_list.new_account)
+            }),
+            ("validator_system.validator_list.copied_count", |s| {
+                s.validator_system.validator_list.copied_count += 1
+            }),
+            ("validator_system.manager_authority", |s| {
+                key(&mut s.validator_system.manager_authority)
+            }),
+            ("validator_system.total_validator_score", |s| {
+                s.validator_system.total_validator_score += 1
+            }),
+            ("validator_system.total_active_balance", |s| {
+                s.validator_system.total_active_balance += 1
+            }),
+            ("validator_system.auto_add_validator_enabled", |s| {
+                s.validator_system.auto_add_validator_enabled += 1
+            }),
+            ("liq_pool.lp_mint", |s| key(&mut s.liq_pool.lp_mint)),
+            ("liq_pool.lp_mint_authority_bump_seed", |s| {
+                s.liq_pool.lp_mint_authority_bump_seed += 1
+            }),
+            ("liq_pool.sol_leg_bump_seed", |s| {
+                s.liq_pool.sol_leg_bump_seed += 1
+            }),
+            ("liq_pool.msol_leg_authority_bump_seed", |s| {
+                s.liq_pool.msol_leg_authority_bump_seed += 1
+            }),
+            ("liq_pool.msol_leg", |s| key(&mut s.liq_pool.msol_leg)),
+            ("liq_pool.lp_liquidity_target", |s| {
+                s.liq_pool.lp_liquidity_target += 1
+            }),
+            ("liq_pool.lp_max_fee", |s| fee(&mut s.liq_pool.lp_max_fee)),
+            ("liq_pool.lp_min_fee", |s| fee(&mut s.liq_pool.lp_min_fee)),
+            ("liq_pool.treasury_cut", |s| {
+                fee(&mut s.liq_pool.treasury_cut)
+            }),
+            ("liq_pool.lp_supply", |s| s.liq_pool.lp_supply += 1),
+            ("liq_pool.lent_from_sol_leg", |s| {
+                s.liq_pool.lent_from_sol_leg += 1
+            }),
+            ("liq_pool.liquidity_sol_cap", |s| {
+                s.liq_pool.liquidity_sol_cap += 1
+            }),
+            ("available_reserve_balance", |s| {
+                s.available_reserve_balance += 1
+            }),
+            ("msol_supply", |s| s.msol_supply += 1),
+            ("msol_price", |s| s.msol_price += 1),
+            ("circulating_ticket_count", |s| {
+                s.circulating_ticket_count += 1
+            }),
+            ("circulating_ticket_balance", |s| {
+                s.circulating_ticket_balance += 1
+            }),
+            ("lent_from_reserve", |s| s.lent_from_reserve += 1),
+            ("min_deposit", |s| s.min_deposit += 1),
+            ("min_withdraw", |s| s.min_withdraw += 1),
+            ("staking_sol_cap", |s| s.staking_sol_cap += 1),
+            ("emergency_cooling_down", |s| s.emergency_cooling_down += 1),
+        ]
+    }
+
+    #[test]
+    fn every_field_is_compared() {
+        let before = Marinade::default();
+        assert!(diff(&before, &before).is_empty());
+        let mutations = mutations();
+        let mut all = before.clone();
+        for (path, mutate) in &mutations {
+            let mut after = before.clone();
+            mutate(&mut after);
+            let changes = diff(&before, &after);
+            assert_eq!(changes.len(), 1, "{}", path);
+            assert_eq!(changes[0].path, *path);
+            mutate(&mut all);
+        }
+        // declaration order
+        let paths: Vec<String> = diff(&before, &all)
+            .into_iter()
+            .map(|change| change.path)
+            .collect();
+        assert_eq!(
+            paths,
+            mutations
+                .iter()
+                .map(|(path, _)| path.to_string())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn severities_and_render() {
+        let before = Marinade::default();
+        let after = Marinade {
+            msol_supply: 10,
+            reward_fee: Fee::from_basis_points(200),
+            liq_pool: LiqPool {
+                lp_mint: Pubkey::new_unique(),
+                ..LiqPool::default()
+            },
+            ..Marinade::default()
+        };
+        let changes = diff(&before, &after);
+        let severities: Vec<(&str, Severity)> = changes
+            .iter()
+            .map(|change| (change.path.as_str(), change.severity))
+            .collect();
+        assert_eq!(
+            severities,
+            vec![
+                ("reward_fee", Severity::Warning),
+                ("liq_pool.lp_mint", Severity::Critical),
+                ("msol_supply", Severity::Info),
+            ]
+        );
+        assert_eq!(changes[2].before, "0");
+        assert_eq!(changes[2].after, "10");
+
+        let rendered = render(&changes);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("[CRITICAL] liq_pool.lp_mint: "));
+        assert!(lines[1].starts_with("[WARNING] reward_fee: "));
+        assert_eq!(lines[2], "[INFO] msol_supply: 0 -> 10");
+        assert_eq!(render(&[]), "");
+    }
+}
@@ -0,0 +1,344 @@
+//! Preflight of native stake account deposits
+
+use solana_program::{
+    clock::Clock, program_error::ProgramError, pubkey::Pubkey, stake::state::StakeState,
+};
+
+use crate::{error::CommonError, state::marinade::Marinade};
+
+/// Rules a stake account must pass to be deposited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepositStakeRule {
+    /// `checks::check_stake_amount_and_validator` does not apply: it compares a stake with
+    /// its `StakeRecord` (recorded validator and `last_update_delegated_lamports`) and a
+    /// deposited stake has no record yet. Only its not delegated case is checked here,
+    /// failing with the same `StakeNotDelegated`
+    Delegated,
+    /// voter is in the validator list or `auto_add_validator_enabled` is set
+    ValidatorAccepted,
+    NoLockup,
+    /// activated before the current epoch and not deactivating.
+    /// Fails with `StakeNotDelegated` although the stake is delegated, so only `reason` tells
+    /// an activating stake from a deactivating one
+    FullyActive,
+    MinStake,
+    StakerIsSigner,
+    WithdrawerIsSigner,
+}
+
+#[derive(Clone, Debug)]
+pub struct RuleFailure {
+    pub rule: DepositStakeRule,
+    pub error: CommonError,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StakeDepositEligibility {
+    pub validator_vote: Option<Pubkey>,
+    /// index in the validator list. `None` if not listed
+    pub validator_index: Option<u32>,
+    /// the validator is not listed and will be added by the deposit
+    pub adds_validator: bool,
+    pub delegated_lamports: u64,
+    pub failures: Vec<RuleFailure>,
+}
+
+impl StakeDepositEligibility {
+    pub fn is_eligible(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub fn failed(&self, rule: DepositStakeRule) -> bool {
+        self.failures.iter().any(|failure| failure.rule == rule)
+    }
+
+    fn fail(&mut self, rule: DepositStakeRule, error: CommonError, reason: String) {
+        self.failures.push(RuleFailure {
+            rule,
+            error,
+            reason,
+        })
+    }
+}
+
+/// Evaluates all rules of depositing `stake_state` signed by `signer` (staker and withdrawer)
+pub fn check_stake_deposit_eligibility(
+    state: &Marinade,
+    validator_list_data: &[u8],
+    stake_state: &StakeState,
+    signer: &Pubkey,
+    clock: &Clock,
+) -> Result<StakeDepositEligibility, ProgramError> {
+    let mut report = StakeDepositEligibility::default();
+
+    let delegation = match stake_state.delegation() {
+        Some(delegation) => delegation,
+        None => {
+            report.fail(
+                DepositStakeRule::Delegated,
+                CommonError::StakeNotDelegated,
+                "Stake account is not delegated".to_string(),
+            );
+            return Ok(report);
+        }
+    };
+    report.validator_vote = Some(delegation.voter_pubkey);
+    report.delegated_lamports = delegation.stake;
+
+    for index in 0..state.validator_system.validator_count() {
+        let validator = state.validator_system.get(validator_list_data, index)?;
+        if validator.validator_account == delegation.voter_pubkey {
+            report.validator_index = Some(index);
+            break;
+        }
+    }
+    if report.validator_index.is_none() {
+        if state.validator_system.auto_add_validator_enabled != 0 {
+            report.adds_validator = true;
+        } else {
+            report.fail(
+                DepositStakeRule::ValidatorAccepted,
+                CommonError::InvalidValidator,
+                format!(
+                    "Validator {} is not in the list and auto add is disabled",
+                    delegation.voter_pubkey
+                ),
+            );
+        }
+    }
+
+    if let Some(lockup) = stake_state.lockup() {
+        if lockup.is_in_force(clock, None) {
+            report.fail(
+                DepositStakeRule::NoLockup,
+                CommonError::AccountWithLockup,
+                format!(
+                    "Lockup is in force until epoch {} / timestamp {}",
+                    lockup.epoch, lockup.unix_timestamp
+                ),
+            );
+        }
+    }
+
+    if delegation.deactivation_epoch != u64::MAX {
+        report.fail(
+            DepositStakeRule::FullyActive,
+            CommonError::StakeNotDelegated,
+            format!(
+                "Stake is deactivating since epoch {}",
+                delegation.deactivation_epoch
+            ),
+        );
+    } else if delegation.activation_epoch >= clock.epoch {
+        report.fail(
+            DepositStakeRule::FullyActive,
+            CommonError::StakeNotDelegated,
+            format!(
+                "Stake is activating in epoch {}. Wait for the next epoch",
+                delegation.activation_epoch
+            ),
+        );
+    }
+
+    if delegation.stake < state.stake_system.min_stake {
+        report.fail(
+            DepositStakeRule::MinStake,
+            CommonError::NumberTooLow,
+            format!(
+                "Stake {} is lower than min_stake {}",
+                delegation.stake, state.stake_system.min_stake
+            ),
+        );
+    }
+
+    if let Some(authorized) = stake_state.authorized() {
+        if authorized.staker != *signer {
+            report.fail(
+                DepositStakeRule::StakerIsSigner,
+                CommonError::UnexpectedAccount,
+                format!("Staker {} is not the signer {}", authorized.staker, signer),
+            );
+        }
+        if authorized.withdrawer != *signer {
+            report.fail(
+                DepositStakeRule::WithdrawerIsSigner,
+                CommonError::UnexpectedAccount,
+                format!(
+                    "Withdrawer {} is not the signer {}",
+                    authorized.withdrawer, signer
+                ),
+            );
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{list::List, validator_system::ValidatorRecord};
+    use borsh::BorshSerialize;
+    use solana_program::stake::state::{Authorized, Lockup, Meta, Stake};
+
+    fn state_with_validator(validator: Pubkey) -> (Marinade, Vec<u8>) {
+        let mut data = ValidatorRecord::DISCRIMINATOR.to_vec();
+        ValidatorRecord {
+            validator_account: validator,
+            ..ValidatorRecord::default()
+        }
+        .serialize(&mut data)
+        .unwrap();
+        let mut state = Marinade::default();
+        state.stake_system.min_stake = 1_000;
+        state.validator_system.validator_list = List {
+            item_size: ValidatorRecord::default().try_to_vec().unwrap().len() as u32,
+            count: 1,
+            ..List::default()
+        };
+        (state, data)
+    }
+
+    fn stake_state(signer: Pubkey, voter: Pubkey, activation_epoch: u64) -> StakeState {
+        let meta = Meta {
+            authorized: Authorized {
+                staker: signer,
+                withdrawer: signer,
+            },
+            ..Meta::default()
+        };
+        let mut stake = Stake::default();
+        stake.delegation.voter_pubkey = voter;
+        stake.delegation.stake = 2_000;
+        stake.delegation.activation_epoch = activation_epoch;
+        stake.delegation.deactivation_epoch = u64::MAX;
+        StakeState::Stake(meta, stake)
+    }
+
+    fn failed_rules(report: &StakeDepositEligibility) -> Vec<DepositStakeRule> {
+        report.failures.iter().map(|failure| failure.rule).collect()
+    }
+
+    fn clock(epoch: u64) -> Clock {
+        Clock {
+            epoch,
+            ..Clock::default()
+        }
+    }
+
+    #[test]
+    fn eligible_stake() {
+        let (validator, signer) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (state, data) = state_with_validator(validator);
+        let stake = stake_state(signer, validator, 5);
+        let report =
+            check_stake_deposit_eligibility(&state, &data, &stake, &signer, &clock(10)).unwrap();
+        assert!(report.is_eligible(), "{:?}", report.failures);
+        assert_eq!(report.validator_index, Some(0));
+        assert!(!report.adds_validator);
+        assert_eq!(report.delegated_lamports, 2_000);
+    }
+
+    #[test]
+    fn fully_active() {
+        let (validator, signer) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (state, data) = state_with_validator(validator);
+        let activating = stake_state(signer, validator, 10);
+        let report =
+            check_stake_deposit_eligibility(&state, &data, &activating, &signer, &clock(10))
+                .unwrap();
+        assert_eq!(failed_rules(&report), vec![DepositStakeRule::FullyActive]);
+
+        let mut deactivating = stake_state(signer, validator, 5);
+        if let StakeState::Stake(_, stake) = &mut deactivating {
+            stake.delegation.deactivation_epoch = 10;
+        }
+        let report =
+            check_stake_deposit_eligibility(&state, &data, &deactivating, &signer, &clock(10))
+                .unwrap();
+        assert!(report.failed(DepositStakeRule::FullyActive));
+        assert_eq!(report.failures[0].error, CommonError::StakeNotDelegated);
+
+        let report = check_stake_deposit_eligibility(
+            &state,
+            &data,
+            &StakeState::Uninitialized,
+            &signer,
+            &clock(10),
+        )
+        .unwrap();
+        assert_eq!(failed_rules(&report), vec![DepositStakeRule::Delegated]);
+    }
+
+    #[test]
+    fn lockup() {
+        let (validator, signer) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (state, data) = state_with_validator(validator);
+        let mut stake = stake_state(signer, validator, 5);
+        if let StakeState::Stake(meta, _) = &mut stake {
+            meta.lockup = Lockup {
+                epoch: 20,
+                ..Lockup::default()
+            };
+        }
+        let report =
+            check_stake_deposit_eligibility(&state, &data, &stake, &signer, &clock(10)).unwrap();
+        assert_eq!(failed_rules(&report), vec![DepositStakeRule::NoLockup]);
+        assert_eq!(report.failures[0].error, CommonError::AccountWithLockup);
+        let report =
+            check_stake_deposit_eligibility(&state, &data, &stake, &signer, &clock(20)).unwrap();
+        assert!(report.is_eligible());
+    }
+
+    #[test]
+    fn authorities() {
+        let (validator, signer) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (state, data) = state_with_validator(validator);
+        let other = Pubkey::new_unique();
+        let mut stake = stake_state(signer, validator, 5);
+        if let StakeState::Stake(meta, _) = &mut stake {
+            meta.authorized.staker = other;
+        }
+        let report =
+            check_stake_deposit_eligibility(&state, &data, &stake, &signer, &clock(10)).unwrap();
+        assert_eq!(
+            failed_rules(&report),
+            vec![DepositStakeRule::StakerIsSigner]
+        );
+
+        if let StakeState::Stake(meta, _) = &mut stake {
+            meta.authorized = Authorized {
+                staker: signer,
+                withdrawer: other,
+            };
+        }
+        let report =
+            check_stake_deposit_eligibility(&state, &data, &stake, &signer, &clock(10)).unwrap();
+        assert_eq!(
+            failed_rules(&report),
+            vec![DepositStakeRule::WithdrawerIsSigner]
+        );
+        assert_eq!(report.failures[0].error, CommonError::UnexpectedAccount);
+    }
+
+    #[test]
+    fn unknown_validator() {
+        let signer = Pubkey::new_unique();
+        let (mut state, data) = state_with_validator(Pubkey::new_unique());
+        let stake = stake_state(signer, Pubkey::new_unique(), 5);
+        let report =
+            check_stake_deposit_eligibility(&state, &data, &stake, &signer, &clock(10)).unwrap();
+        assert_eq!(
+            failed_rules(&report),
+            vec![DepositStakeRule::ValidatorAccepted]
+        );
+        assert_eq!(report.validator_index, None);
+
+        state.validator_system.auto_add_validator_enabled = 1;
+        let report =
+            check_stake_deposit_eligibility(&state, &data, &stake, &signer, &clock(10)).unwrap();
+        assert!(report.is_eligible());
+        assert!(report.adds_validator);
+    }
+}
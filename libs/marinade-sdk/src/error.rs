@@ -1,8 +1,12 @@
+use std::convert::TryFrom;
+
 use derive_more::Display;
 use solana_program::program_error::ProgramError;
 
+use crate::ID;
+
 #[repr(u32)]
-#[derive(Debug, Clone, Copy, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
 pub enum CommonError {
     WrongReserveOwner,
     NonEmptyReserveData,
@@ -36,8 +40,217 @@ pub enum CommonError {
 
 const ERROR_CODE_OFFSET: u32 = 300;
 
+/// Raw code returned by `check_staking_cap` and `check_liquidity_cap`
+pub const CAP_REACHED_ERROR_CODE: u32 = 3782;
+
+impl CommonError {
+    pub const ALL: &'static [CommonError] = &[
+        CommonError::WrongReserveOwner,
+        CommonError::NonEmptyReserveData,
+        CommonError::InvalidInitialReserveLamports,
+        CommonError::ZeroValidatorChunkSize,
+        CommonError::TooBigValidatorChunkSize,
+        CommonError::ZeroCreditChunkSize,
+        CommonError::TooBigCreditChunkSize,
+        CommonError::TooLowCreditFee,
+        CommonError::InvalidMintAuthority,
+        CommonError::MintHasInitialSupply,
+        CommonError::InvalidOwnerFeeState,
+        CommonError::InvalidProgramId,
+        CommonError::UnexpectedAccount,
+        CommonError::CalculationFailure,
+        CommonError::AccountWithLockup,
+        CommonError::NumberTooLow,
+        CommonError::NumberTooHigh,
+        CommonError::FeeTooHigh,
+        CommonError::FeesWrongWayRound,
+        CommonError::LiquidityTargetTooLow,
+        CommonError::TicketNotDue,
+        CommonError::TicketNotReady,
+        CommonError::WrongBeneficiary,
+        CommonError::StakeAccountNotUpdatedYet,
+        CommonError::StakeNotDelegated,
+        CommonError::StakeAccountIsEmergencyUnstaking,
+        CommonError::InsufficientLiquidity,
+        CommonError::InvalidValidator,
+    ];
+
+    /// Error code as returned by the program in `ProgramError::Custom`
+    pub fn code(self) -> u32 {
+        self as u32 + ERROR_CODE_OFFSET
+    }
+
+    /// Inverse of `code`
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.iter().copied().find(|e| e.code() == code)
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            CommonError::WrongReserveOwner => "Wrong reserve owner. Must be a system account",
+            CommonError::NonEmptyReserveData => "Reserve must have no data, but has data",
+            CommonError::InvalidInitialReserveLamports => "Invalid initial reserve lamports",
+            CommonError::ZeroValidatorChunkSize => "Zero validator chunk size",
+            CommonError::TooBigValidatorChunkSize => "Too big validator chunk size",
+            CommonError::ZeroCreditChunkSize => "Zero credit chunk size",
+            CommonError::TooBigCreditChunkSize => "Too big credit chunk size",
+            CommonError::TooLowCreditFee => "Too low credit fee",
+            CommonError::InvalidMintAuthority => "Invalid mint authority",
+            CommonError::MintHasInitialSupply => "Non empty initial mint supply",
+            CommonError::InvalidOwnerFeeState => "Invalid owner fee state",
+            CommonError::InvalidProgramId => "Invalid program id. For using program from another account please update id in the code",
+            CommonError::UnexpectedAccount => "Unexpected account",
+            CommonError::CalculationFailure => "Calculation failure",
+            CommonError::AccountWithLockup => "You can't deposit a stake-account with lockup",
+            CommonError::NumberTooLow => "Number too low",
+            CommonError::NumberTooHigh => "Number too high",
+            CommonError::FeeTooHigh => "Fee too high",
+            CommonError::FeesWrongWayRound => "Min fee > max fee",
+            CommonError::LiquidityTargetTooLow => "Liquidity target too low",
+            CommonError::TicketNotDue => "Ticket not due. Wait more epochs",
+            CommonError::TicketNotReady => "Ticket not ready. Wait a few hours and try again",
+            CommonError::WrongBeneficiary => "Wrong Ticket Beneficiary",
+            CommonError::StakeAccountNotUpdatedYet => "Stake Account not updated yet",
+            CommonError::StakeNotDelegated => "Stake Account not delegated",
+            CommonError::StakeAccountIsEmergencyUnstaking => "Stake Account is emergency unstaking",
+            CommonError::InsufficientLiquidity => "Insufficient Liquidity in the Liquidity Pool",
+            CommonError::InvalidValidator => "Invalid validator",
+        }
+    }
+}
+
+/// Returns the original error if it is not a `CommonError`
+impl TryFrom<ProgramError> for CommonError {
+    type Error = ProgramError;
+
+    fn try_from(e: ProgramError) -> Result<Self, Self::Error> {
+        match e {
+            ProgramError::Custom(code) => Self::from_code(code).ok_or(e),
+            _ => Err(e),
+        }
+    }
+}
+
+/// Error of the marinade program decoded by a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum MarinadeError {
+    Common(CommonError),
+    StakingCapReached,
+    LiquidityCapReached,
+    /// `CAP_REACHED_ERROR_CODE` without logs telling which cap it was
+    CapReached,
+}
+
+impl MarinadeError {
+    pub fn from_code(code: u32) -> Option<Self> {
+        if code == CAP_REACHED_ERROR_CODE {
+            Some(MarinadeError::CapReached)
+        } else {
+            CommonError::from_code(code).map(MarinadeError::Common)
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            MarinadeError::Common(e) => e.message(),
+            MarinadeError::StakingCapReached => "Staking cap reached",
+            MarinadeError::LiquidityCapReached => "Liquidity cap reached",
+            MarinadeError::CapReached => "Staking or liquidity cap reached",
+        }
+    }
+
+    /// Finds the error the marinade program failed with in transaction logs
+    pub fn from_logs<S: AsRef<str>>(logs: &[S]) -> Option<Self> {
+        let failed_prefix = format!("Program {} failed: custom program error: 0x", ID);
+        let (index, code) = logs.iter().enumerate().find_map(|(index, line)| {
+            line.as_ref()
+                .strip_prefix(&failed_prefix)
+                .and_then(|hex| u32::from_str_radix(hex.trim(), 16).ok())
+                .map(|code| (index, code))
+        })?;
+        let error = Self::from_code(code)?;
+        if error != MarinadeError::CapReached {
+            return Some(error);
+        }
+        // the cap is only known from the message logged by the check
+        Some(
+            logs[..index]
+                .iter()
+                .rev()
+                .find_map(|line| {
+                    let message = line.as_ref().strip_prefix("Program log: ")?;
+                    if message.starts_with("Staking cap reached") {
+                        Some(MarinadeError::StakingCapReached)
+                    } else if message.starts_with("Liquidity cap reached") {
+                        Some(MarinadeError::LiquidityCapReached)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(MarinadeError::CapReached),
+        )
+    }
+}
+
+impl TryFrom<ProgramError> for MarinadeError {
+    type Error = ProgramError;
+
+    fn try_from(e: ProgramError) -> Result<Self, Self::Error> {
+        match e {
+            ProgramError::Custom(code) => Self::from_code(code).ok_or(e),
+            _ => Err(e),
+        }
+    }
+}
+
 impl From<CommonError> for ProgramError {
     fn from(e: CommonError) -> Self {
-        ProgramError::Custom(e as u32 + ERROR_CODE_OFFSET)
+        ProgramError::Custom(e.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_round_trip() {
+        for e in CommonError::ALL {
+            assert_eq!(CommonError::from_code(e.code()), Some(*e));
+            assert_eq!(CommonError::try_from(ProgramError::from(*e)), Ok(*e));
+        }
+        assert_eq!(CommonError::from_code(6116), None);
+        assert_eq!(
+            CommonError::try_from(ProgramError::InvalidArgument),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(
+            MarinadeError::try_from(ProgramError::Custom(CAP_REACHED_ERROR_CODE)),
+            Ok(MarinadeError::CapReached)
+        );
+    }
+
+    #[test]
+    fn from_logs() {
+        let failed =
+            |code: u32| format!("Program {} failed: custom program error: {:#x}", ID, code);
+        let logs = vec![
+            format!("Program {} invoke [1]", ID),
+            "Program log: Liquidity cap reached 11/10".to_string(),
+            failed(CAP_REACHED_ERROR_CODE),
+        ];
+        assert_eq!(
+            MarinadeError::from_logs(&logs),
+            Some(MarinadeError::LiquidityCapReached)
+        );
+        assert_eq!(
+            MarinadeError::from_logs(&[failed(CommonError::InsufficientLiquidity.code())]),
+            Some(MarinadeError::Common(CommonError::InsufficientLiquidity))
+        );
+        assert_eq!(
+            MarinadeError::from_logs(&[failed(CAP_REACHED_ERROR_CODE)]),
+            Some(MarinadeError::CapReached)
+        );
+        assert_eq!(MarinadeError::from_logs(&["Program log: ok"]), None);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,352 @@
+//! Anchor IDL generation from the SDK types
+//!
+//! Types are described by their `BorshSchema` so the IDL can not drift from the
+//! layouts this crate (de)serializes.
+
+use std::collections::{BTreeMap, HashMap};
+
+use borsh::schema::{Declaration, Definition, Fields};
+use borsh::BorshSchema;
+use serde_json::{json, Value};
+
+use crate::{
+    error::CommonError,
+    instructions::{config_lp::ConfigLpData, initialize::InitializeData},
+    state::{
+        delayed_unstake_ticket::DelayedUnstakeTicket, marinade::Marinade,
+        stake_system::StakeRecord, validator_system::ValidatorRecord,
+    },
+    ID,
+};
+
+/// Program name used by the anchor client
+pub const PROGRAM_NAME: &str = "marinade_finance";
+
+/// Collects IDL sections. Every struct referenced from instructions or accounts
+/// is registered in `types` once.
+#[derive(Default)]
+pub struct IdlBuilder {
+    instructions: Vec<Value>,
+    accounts: Vec<Value>,
+    types: BTreeMap<String, Value>,
+}
+
+impl IdlBuilder {
+    /// Adds an instruction taking `T` as a single argument
+    pub fn instruction<T: BorshSchema>(
+        mut self,
+        name: &str,
+        arg_name: &str,
+        accounts: Vec<Value>,
+    ) -> Self {
+        let arg_type = self.add_type::<T>();
+        self.instructions.push(json!({
+            "name": camel_case(name),
+            "accounts": accounts,
+            "args": [{ "name": camel_case(arg_name), "type": arg_type }],
+        }));
+        self
+    }
+
+    /// Adds an account. `name` must be the anchor account name the discriminator was made from
+    pub fn account<T: BorshSchema>(mut self, name: &str) -> Self {
+        let container = T::schema_container();
+        let fields = self.struct_fields(&container.declaration, &container.definitions);
+        self.accounts.push(json!({
+            "name": name,
+            "type": { "kind": "struct", "fields": fields },
+        }));
+        self
+    }
+
+    /// Adds a type which is not referenced by any account or instruction (list items for example)
+    pub fn ty<T: BorshSchema>(mut self) -> Self {
+        self.add_type::<T>();
+        self
+    }
+
+    pub fn build(self, errors: &[CommonError]) -> Value {
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "name": PROGRAM_NAME,
+            "instructions": self.instructions,
+            "accounts": self.accounts,
+            "types": self.types.into_values().collect::<Vec<_>>(),
+            "errors": errors
+                .iter()
+                .map(|e| {
+                    json!({ "code": e.code(), "name": format!("{:?}", e), "msg": e.message() })
+                })
+                .collect::<Vec<_>>(),
+            "metadata": { "address": ID.to_string() },
+        })
+    }
+
+    fn add_type<T: BorshSchema>(&mut self) -> Value {
+        let container = T::schema_container();
+        self.idl_type(&container.declaration, &container.definitions)
+    }
+
+    fn idl_type(
+        &mut self,
+        declaration: &Declaration,
+        definitions: &HashMap<Declaration, Definition>,
+    ) -> Value {
+        match declaration.as_str() {
+            "bool" | "u8" | "i8" | "u16" | "i16" | "u32" | "i32" | "u64" | "i64" | "u128"
+            | "i128" | "f32" | "f64" | "string" => return json!(declaration),
+            "Pubkey" => return json!("publicKey"),
+            _ => {}
+        }
+        match definitions
+            .get(declaration)
+            .unwrap_or_else(|| panic!("No schema definition for {}", declaration))
+        {
+            Definition::Array { length, elements } => {
+                json!({ "array": [self.idl_type(elements, definitions), length] })
+            }
+            Definition::Sequence { elements } => {
+                json!({ "vec": self.idl_type(elements, definitions) })
+            }
+            Definition::Enum { variants } if declaration.starts_with("Option<") => {
+                json!({ "option": self.idl_type(&variants[1].1, definitions) })
+            }
+            Definition::Enum { variants } => {
+                let variants: Vec<Value> = variants
+                    .iter()
+                    .map(|(name, variant)| match definitions.get(variant) {
+                        Some(Definition::Struct {
+                            fields: Fields::Empty,
+                        })
+                        | None => json!({ "name": name }),
+                        Some(_) => {
+                            json!({ "name": name, "fields": [self.idl_type(variant, definitions)] })
+                        }
+                    })
+                    .collect();
+                self.types.insert(
+                    declaration.clone(),
+                    json!({
+                        "name": declaration,
+                        "type": { "kind": "enum", "variants": variants },
+                    }),
+                );
+                json!({ "defined": declaration })
+            }
+            Definition::Struct { .. } => {
+                if !self.types.contains_key(declaration) {
+                    let fields = self.struct_fields(declaration, definitions);
+                    self.types.insert(
+                        declaration.clone(),
+                        json!({
+                            "name": declaration,
+                            "type": { "kind": "struct", "fields": fields },
+                        }),
+                    );
+                }
+                json!({ "defined": declaration })
+            }
+            Definition::Tuple { .. } => {
+                panic!("Tuple {} can not be expressed in anchor IDL", declaration)
+            }
+        }
+    }
+
+    fn struct_fields(
+        &mut self,
+        declaration: &Declaration,
+        definitions: &HashMap<Declaration, Definition>,
+    ) -> Vec<Value> {
+        match definitions.get(declaration) {
+            Some(Definition::Struct {
+                fields: Fields::NamedFields(fields),
+            }) => fields
+                .iter()
+                .map(|(name, field_type)| {
+                    json!({
+                        "name": camel_case(name),
+                        "type": self.idl_type(field_type, definitions),
+                    })
+                })
+                .collect(),
+            Some(Definition::Struct {
+                fields: Fields::Empty,
+            }) => vec![],
+            _ => panic!("{} is not a struct with named fields", declaration),
+        }
+    }
+}
+
+/// Instruction account entry
+pub fn idl_account(name: &str, is_mut: bool, is_signer: bool) -> Value {
+    json!({ "name": camel_case(name), "isMut": is_mut, "isSigner": is_signer })
+}
+
+/// Nested accounts struct entry
+pub fn idl_accounts(name: &str, accounts: Vec<Value>) -> Value {
+    json!({ "name": camel_case(name), "accounts": accounts })
+}
+
+pub fn camel_case(snake: &str) -> String {
+    let mut result = String::with_capacity(snake.len());
+    let mut upper = false;
+    for c in snake.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            result.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// IDL of the marinade program as far as it is covered by this SDK
+pub fn marinade_idl() -> Value {
+    IdlBuilder::default()
+        .instruction::<InitializeData>(
+            "initialize",
+            "data",
+            vec![
+                idl_account("creator_authority", false, true),
+                idl_account("state", true, false),
+                idl_account("reserve_pda", false, false),
+                idl_account("stake_list", true, false),
+                idl_account("validator_list", true, false),
+                idl_account("msol_mint", false, false),
+                idl_account("operational_sol_account", false, false),
+                idl_accounts(
+                    "liq_pool",
+                    vec![
+                        idl_account("lp_mint", false, false),
+                        idl_account("sol_leg_pda", false, false),
+                        idl_account("msol_leg", false, false),
+                    ],
+                ),
+                idl_account("treasury_msol_account", false, false),
+                idl_account("clock", false, false),
+                idl_account("rent", false, false),
+            ],
+        )
+        .instruction::<ConfigLpData>(
+            "config_lp",
+            "params",
+            vec![
+                idl_account("state", true, false),
+                idl_account("admin_authority", false, true),
+            ],
+        )
+        // names match the discriminators of the deployed program
+        .account::<Marinade>("State")
+        .account::<DelayedUnstakeTicket>("TicketAccountData")
+        .ty::<StakeRecord>()
+        .ty::<ValidatorRecord>()
+        .build(CommonError::ALL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::pubkey::Pubkey;
+
+    #[derive(BorshSchema)]
+    #[allow(dead_code)]
+    struct Sample {
+        maybe_amount: Option<u64>,
+        keys: Vec<Pubkey>,
+        seed: [u8; 4],
+        record: StakeRecord,
+    }
+
+    fn field<'a>(entry: &'a Value, name: &str) -> &'a Value {
+        entry["type"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|field| field["name"] == name)
+            .unwrap_or_else(|| panic!("No field {}", name))
+    }
+
+    #[test]
+    fn option_vec_and_array_mapping() {
+        let idl = IdlBuilder::default()
+            .instruction::<Sample>("do_sample", "sample_args", vec![])
+            .build(&[]);
+        let instruction = &idl["instructions"][0];
+        assert_eq!(instruction["name"], "doSample");
+        assert_eq!(
+            instruction["args"],
+            json!([{ "name": "sampleArgs", "type": { "defined": "Sample" } }])
+        );
+        let types = idl["types"].as_array().unwrap();
+        let sample = types.iter().find(|ty| ty["name"] == "Sample").unwrap();
+        assert_eq!(
+            field(sample, "maybeAmount")["type"],
+            json!({ "option": "u64" })
+        );
+        assert_eq!(field(sample, "keys")["type"], json!({ "vec": "publicKey" }));
+        assert_eq!(field(sample, "seed")["type"], json!({ "array": ["u8", 4] }));
+        assert_eq!(
+            field(sample, "record")["type"],
+            json!({ "defined": "StakeRecord" })
+        );
+        // nested structs are registered once
+        assert_eq!(
+            types
+                .iter()
+                .filter(|ty| ty["name"] == "StakeRecord")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn marinade_accounts_types_and_errors() {
+        let idl = marinade_idl();
+        assert_eq!(idl["name"], PROGRAM_NAME);
+        assert_eq!(idl["metadata"]["address"], ID.to_string());
+
+        let accounts: Vec<&Value> = idl["accounts"].as_array().unwrap().iter().collect();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0]["name"], "State");
+        assert_eq!(field(accounts[0], "msolMint")["type"], "publicKey");
+        assert_eq!(
+            field(accounts[0], "liqPool")["type"],
+            json!({ "defined": "LiqPool" })
+        );
+        assert_eq!(accounts[1]["name"], "TicketAccountData");
+        assert_eq!(field(accounts[1], "createdEpoch")["type"], "u64");
+
+        let type_names: Vec<&str> = idl["types"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|ty| ty["name"].as_str().unwrap())
+            .collect();
+        for name in [
+            "Fee",
+            "LiqPool",
+            "List",
+            "StakeRecord",
+            "StakeSystem",
+            "ValidatorRecord",
+            "ValidatorSystem",
+        ] {
+            assert!(type_names.contains(&name), "No type {}", name);
+        }
+
+        let errors = idl["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), CommonError::ALL.len());
+        let first = CommonError::ALL[0];
+        assert_eq!(
+            errors[0],
+            json!({
+                "code": first.code(),
+                "name": "WrongReserveOwner",
+                "msg": first.message(),
+            })
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! Lookup of validator and stake records by pubkey
+//!
+//! Indices are built from raw list data once. `refresh` re-reads only the items
+//! whose bytes changed since the last build (e.g. after a push or swap-remove).
+
+use std::{collections::HashMap, ops::Range};
+
+use borsh::BorshDeserialize;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey, stake::state::StakeState};
+
+use crate::state::{
+    list::List,
+    stake_system::{StakeRecord, StakeSystem},
+    validator_system::{ValidatorRecord, ValidatorSystem},
+};
+
+#[derive(Clone, Debug)]
+struct ListIndex<R> {
+    list_name: &'static str,
+    key: fn(&R) -> Pubkey,
+    item_size: u32,
+    /// item bytes of the last read
+    raw: Vec<u8>,
+    records: Vec<R>,
+    by_key: HashMap<Pubkey, u32>,
+}
+
+impl<R: BorshDeserialize> ListIndex<R> {
+    fn new(list_name: &'static str, key: fn(&R) -> Pubkey) -> Self {
+        Self {
+            list_name,
+            key,
+            item_size: 0,
+            raw: Vec::new(),
+            records: Vec::new(),
+            by_key: HashMap::new(),
+        }
+    }
+
+    /// Returns indices of the changed and added records and the indices removed from the tail
+    fn refresh(
+        &mut self,
+        list: &List,
+        data: &[u8],
+    ) -> Result<(Vec<u32>, Range<u32>), ProgramError> {
+        if list.item_size() != self.item_size {
+            *self = Self::new(self.list_name, self.key);
+            self.item_size = list.item_size();
+        }
+        let item_size = self.item_size as usize;
+        let end = 8 + list.len() as usize * item_size;
+        if data.len() < end {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let items = &data[8..end];
+
+        let mut changed = Vec::new();
+        for index in 0..list.len() {
+            let start = index as usize * item_size;
+            let item = &items[start..start + item_size];
+            if self.raw.get(start..start + item_size) == Some(item) {
+                continue;
+            }
+            let record = list.get::<R>(data, index, self.list_name)?;
+            if let Some(old) = self.records.get(index as usize) {
+                self.remove_key(&(self.key)(old), index);
+            }
+            self.by_key.insert((self.key)(&record), index);
+            if (index as usize) < self.records.len() {
+                self.records[index as usize] = record;
+            } else {
+                self.records.push(record);
+            }
+            changed.push(index);
+        }
+        // removed from the tail
+        let removed = list.len()..(self.records.len() as u32).max(list.len());
+        for index in removed.clone() {
+            let key = (self.key)(&self.records[index as usize]);
+            self.remove_key(&key, index);
+        }
+        self.records.truncate(list.len() as usize);
+        self.raw = items.to_vec();
+        Ok((changed, removed))
+    }
+
+    /// Only if the key was not already moved to another index
+    fn remove_key(&mut self, key: &Pubkey, index: u32) {
+        if self.by_key.get(key) == Some(&index) {
+            self.by_key.remove(key);
+        }
+    }
+
+    fn get(&self, key: &Pubkey) -> Option<(u32, &R)> {
+        self.by_key
+            .get(key)
+            .map(|index| (*index, &self.records[*index as usize]))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidatorIndex(ListIndex<ValidatorRecord>);
+
+impl ValidatorIndex {
+    pub fn build(
+        validator_system: &ValidatorSystem,
+        validator_list_data: &[u8],
+    ) -> Result<Self, ProgramError> {
+        let mut index = Self(ListIndex::new("validator_list", |validator| {
+            validator.validator_account
+        }));
+        index.refresh(validator_system, validator_list_data)?;
+        Ok(index)
+    }
+
+    /// Re-reads changed records. Returns their indices
+    pub fn refresh(
+        &mut self,
+        validator_system: &ValidatorSystem,
+        validator_list_data: &[u8],
+    ) -> Result<Vec<u32>, ProgramError> {
+        Ok(self
+            .0
+            .refresh(&validator_system.validator_list, validator_list_data)?
+            .0)
+    }
+
+    pub fn get(&self, validator_account: &Pubkey) -> Option<(u32, &ValidatorRecord)> {
+        self.0.get(validator_account)
+    }
+
+    pub fn index_of(&self, validator_account: &Pubkey) -> Option<u32> {
+        self.0.by_key.get(validator_account).copied()
+    }
+
+    /// In list order
+    pub fn records(&self) -> &[ValidatorRecord] {
+        &self.0.records
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StakeIndex {
+    index: ListIndex<StakeRecord>,
+    by_validator: HashMap<Pubkey, Vec<u32>>,
+    /// group of every record by index
+    validators: Vec<Option<Pubkey>>,
+}
+
+impl StakeIndex {
+    /// `stake_accounts` give the delegations used to group stakes by validator.
+    /// Stakes without a delegated account there are not grouped
+    pub fn build(
+        stake_system: &StakeSystem,
+        stake_list_data: &[u8],
+        stake_accounts: &[(Pubkey, StakeState)],
+    ) -> Result<Self, ProgramError> {
+        let mut index = Self {
+            index: ListIndex::new("stake_list", |stake| stake.stake_account),
+            by_validator: HashMap::new(),
+            validators: Vec::new(),
+        };
+        index.refresh(stake_system, stake_list_data, stake_accounts)?;
+        Ok(index)
+    }
+
+    /// Re-reads changed records and regroups only them. Returns indices of the changed records.
+    /// Unchanged records keep the group of their delegation at the time they were read
+    pub fn refresh(
+        &mut self,
+        stake_system: &StakeSystem,
+        stake_list_data: &[u8],
+        stake_accounts: &[(Pubkey, StakeState)],
+    ) -> Result<Vec<u32>, ProgramError> {
+        if stake_system.stake_list.item_size() != self.index.item_size {
+            // all records are read again
+            self.by_validator.clear();
+            self.validators.clear();
+        }
+        let (changed, removed) = self
+            .index
+            .refresh(&stake_system.stake_list, stake_list_data)?;
+        for index in removed.chain(changed.iter().copied()) {
+            self.ungroup(index);
+        }
+        self.validators.resize(self.index.records.len(), None);
+        for index in &changed {
+            let stake_account = self.index.records[*index as usize].stake_account;
+            let delegation = stake_accounts
+                .iter()
+                .find(|(address, _)| *address == stake_account)
+                .and_then(|(_, stake_state)| stake_state.delegation());
+            if let Some(delegation) = delegation {
+                let indices = self
+                    .by_validator
+                    .entry(delegation.voter_pubkey)
+                    .or_default();
+                if let Err(position) = indices.binary_search(index) {
+                    indices.insert(position, *index);
+                }
+                self.validators[*index as usize] = Some(delegation.voter_pubkey);
+            }
+        }
+        Ok(changed)
+    }
+
+    fn ungroup(&mut self, index: u32) {
+        let validator = match self
+            .validators
+            .get_mut(index as usize)
+            .and_then(Option::take)
+        {
+            Some(validator) => validator,
+            None => return,
+        };
+        if let Some(indices) = self.by_validator.get_mut(&validator) {
+            indices.retain(|grouped| *grouped != index);
+            if indices.is_empty() {
+                self.by_validator.remove(&validator);
+            }
+        }
+    }
+
+    pub fn get(&self, stake_account: &Pubkey) -> Option<(u32, &StakeRecord)> {
+        self.index.get(stake_account)
+    }
+
+    pub fn index_of(&self, stake_account: &Pubkey) -> Option<u32> {
+        self.index.by_key.get(stake_account).copied()
+    }
+
+    /// Stakes delegated to `validator_account` in list order
+    pub fn by_validator(&self, validator_account: &Pubkey) -> Vec<(u32, &StakeRecord)> {
+        self.by_validator
+            .get(validator_account)
+            .map(|indices| {
+                indices
+                    .iter()
+                    .map(|index| (*index, &self.index.records[*index as usize]))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// In list order
+    pub fn records(&self) -> &[StakeRecord] {
+        &self.index.records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshSerialize;
+    use solana_program::stake::state::{Meta, Stake};
+
+    fn delegated(voter: Pubkey) -> StakeState {
+        let mut stake = Stake::default();
+        stake.delegation.voter_pubkey = voter;
+        StakeState::Stake(Meta::default(), stake)
+    }
+
+    #[test]
+    fn incremental_refresh() {
+        let item_size = StakeRecord::default().try_to_vec().unwrap().len() as u32;
+        let mut data = vec![0u8; List::bytes_for(item_size, 4) as usize];
+        let mut stake_system = StakeSystem {
+            stake_list: List::new(
+                StakeRecord::DISCRIMINATOR,
+                item_size,
+                Pubkey::new_unique(),
+                &mut data,
+                "stake_list",
+            )
+            .unwrap(),
+            ..StakeSystem::default()
+        };
+        let stakes: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        for stake_account in &stakes {
+            let record = StakeRecord {
+                stake_account: *stake_account,
+                ..StakeRecord::default()
+            };
+            stake_system
+                .stake_list
+                .push(&mut data, record, "stake_list")
+                .unwrap();
+        }
+        let (validator_a, validator_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let stake_accounts = vec![
+            (stakes[0], delegated(validator_a)),
+            (stakes[1], delegated(validator_b)),
+            (stakes[2], delegated(validator_a)),
+        ];
+        let mut index = StakeIndex::build(&stake_system, &data, &stake_accounts).unwrap();
+        assert_eq!(index.index_of(&stakes[2]), Some(2));
+        let on_a: Vec<u32> = index
+            .by_validator(&validator_a)
+            .iter()
+            .map(|(index, _)| *index)
+            .collect();
+        assert_eq!(on_a, vec![0, 2]);
+
+        // swap-remove the first stake
+        stake_system
+            .stake_list
+            .remove(&mut data, 0, "stake_list")
+            .unwrap();
+        let changed = index
+            .refresh(&stake_system, &data, &stake_accounts)
+            .unwrap();
+        assert_eq!(changed, vec![0]);
+        assert_eq!(index.index_of(&stakes[0]), None);
+        assert_eq!(index.index_of(&stakes[2]), Some(0));
+        assert_eq!(index.get(&stakes[1]).unwrap().0, 1);
+        assert_eq!(index.records().len(), 2);
+        assert_eq!(index.by_validator(&validator_a).len(), 1);
+        assert!(index
+            .refresh(&stake_system, &data, &stake_accounts)
+            .unwrap()
+            .is_empty());
+
+        // re-delegated stake moves to its new group when its record changes
+        let validator_c = Pubkey::new_unique();
+        let stake_accounts = vec![
+            (stakes[1], delegated(validator_c)),
+            (stakes[2], delegated(validator_a)),
+        ];
+        let record = StakeRecord {
+            stake_account: stakes[1],
+            last_update_epoch: 1,
+            ..StakeRecord::default()
+        };
+        stake_system
+            .stake_list
+            .set(&mut data, 1, record, "stake_list")
+            .unwrap();
+        assert_eq!(
+            index
+                .refresh(&stake_system, &data, &stake_accounts)
+                .unwrap(),
+            vec![1]
+        );
+        assert!(index.by_validator(&validator_b).is_empty());
+        assert_eq!(index.by_validator(&validator_c)[0].0, 1);
+        assert_eq!(index.by_validator(&validator_a)[0].0, 0);
+
+        // removing the last stake ungroups it
+        stake_system
+            .stake_list
+            .remove(&mut data, 1, "stake_list")
+            .unwrap();
+        assert!(index
+            .refresh(&stake_system, &data, &stake_accounts)
+            .unwrap()
+            .is_empty());
+        assert!(index.by_validator(&validator_c).is_empty());
+        assert_eq!(index.records().len(), 1);
+    }
+
+    #[test]
+    fn validator_index() {
+        let item_size = ValidatorRecord::default().try_to_vec().unwrap().len() as u32;
+        let mut data = vec![0u8; List::bytes_for(item_size, 3) as usize];
+        let mut validator_system = ValidatorSystem {
+            validator_list: List::new(
+                ValidatorRecord::DISCRIMINATOR,
+                item_size,
+                Pubkey::new_unique(),
+                &mut data,
+                "validator_list",
+            )
+            .unwrap(),
+            ..ValidatorSystem::default()
+        };
+        let validators: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        for validator_account in &validators {
+            let record = ValidatorRecord {
+                validator_account: *validator_account,
+                score: 1,
+                ..ValidatorRecord::default()
+            };
+            validator_system
+                .validator_list
+                .push(&mut data, record, "validator_list")
+                .unwrap();
+        }
+        let mut index = ValidatorIndex::build(&validator_system, &data).unwrap();
+        assert_eq!(index.index_of(&validators[1]), Some(1));
+        assert_eq!(index.get(&validators[2]).unwrap().1.score, 1);
+        assert_eq!(index.get(&Pubkey::new_unique()), None);
+
+        let record = ValidatorRecord {
+            validator_account: validators[1],
+            score: 5,
+            ..ValidatorRecord::default()
+        };
+        validator_system
+            .validator_list
+            .set(&mut data, 1, record, "validator_list")
+            .unwrap();
+        assert_eq!(index.refresh(&validator_system, &data).unwrap(), vec![1]);
+        assert_eq!(index.get(&validators[1]).unwrap().1.score, 5);
+
+        // swap-remove moves the last validator into the first place
+        validator_system
+            .validator_list
+            .remove(&mut data, 0, "validator_list")
+            .unwrap();
+        assert_eq!(index.refresh(&validator_system, &data).unwrap(), vec![0]);
+        assert_eq!(index.index_of(&validators[0]), None);
+        assert_eq!(index.index_of(&validators[2]), Some(0));
+        assert_eq!(index.index_of(&validators[1]), Some(1));
+        assert_eq!(index.records().len(), 2);
+        assert!(index.refresh(&validator_system, &data).unwrap().is_empty());
+    }
+}
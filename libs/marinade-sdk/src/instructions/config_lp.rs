@@ -1,9 +1,9 @@
 use crate::state::fee::Fee;
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use micro_anchor::{Discriminator, InstructionData, Owner, ToAccountInfos, ToAccountMetas};
 use solana_program::{account_info::AccountInfo, instruction::AccountMeta, pubkey::Pubkey};
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize, BorshSchema)]
 pub struct ConfigLpData {
     pub min_fee: Option<Fee>,
     pub max_fee: Option<Fee>,
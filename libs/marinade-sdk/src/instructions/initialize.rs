@@ -1,5 +1,5 @@
 use solana_program::{pubkey::Pubkey, account_info::AccountInfo};
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use crate::state::fee::Fee;
 
 pub struct InitializeAccountInfos<'info> {
@@ -28,7 +28,7 @@ pub struct LiqPoolInitializeAccountInfos<'info> {
     pub msol_leg: AccountInfo<'info>
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize, BorshSchema)]
 pub struct InitializeData {
     pub admin_authority: Pubkey,
     pub validator_manager_authority: Pubkey,
@@ -41,7 +41,7 @@ pub struct InitializeData {
     pub slots_for_stake_delta: u64,
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize, BorshSchema)]
 pub struct LiqPoolInitializeData {
     pub lp_liquidity_target: u64,
     pub lp_max_fee: Fee,
@@ -0,0 +1,496 @@
+//! Comparison of the virtual counters of the state with the real balances
+//!
+//! `available_reserve_balance`, `msol_supply`, `LiqPool::lp_supply` and the stake records
+//! are only updated by marinade instructions, so transfers, burns and rewards make them
+//! drift from the real accounts until the corresponding `Update*` instruction runs.
+
+use std::fmt::Display;
+
+use solana_program::{program_error::ProgramError, pubkey::Pubkey, stake::state::StakeState};
+use spl_token::state::Mint;
+
+use crate::state::{marinade::Marinade, stake_system::StakeRecord};
+
+/// Real accounts the virtual counters are compared with
+#[derive(Clone, Copy, Debug)]
+pub struct ProtocolBalances<'a> {
+    /// reserve PDA lamports
+    pub reserve_lamports: u64,
+    pub msol_mint: &'a Mint,
+    pub lp_mint: &'a Mint,
+    /// liq pool SOL leg PDA lamports
+    pub sol_leg_lamports: u64,
+    pub validator_list_data: &'a [u8],
+    pub stake_list_data: &'a [u8],
+    /// `(stake account address, stake state)`. Records without an account here are not checked
+    pub stake_accounts: &'a [(Pubkey, StakeState)],
+    /// current epoch (`Clock::epoch`) deciding which update a stake account can get
+    pub epoch: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    AvailableReserveBalance,
+    MsolSupply,
+    LpSupply,
+    /// `last_update_delegated_lamports` of the stake record
+    StakeDelegation {
+        stake_index: u32,
+        stake_account: Pubkey,
+    },
+}
+
+impl Display for Counter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Counter::AvailableReserveBalance => write!(f, "available_reserve_balance"),
+            Counter::MsolSupply => write!(f, "msol_supply"),
+            Counter::LpSupply => write!(f, "liq_pool.lp_supply"),
+            Counter::StakeDelegation {
+                stake_index,
+                stake_account,
+            } => write!(f, "stake #{} {} delegation", stake_index, stake_account),
+        }
+    }
+}
+
+/// Instruction aligning a drifted counter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Update {
+    /// Any of `UpdateActive` or `UpdateDeactivated`
+    AnyStake,
+    UpdateActive {
+        stake_index: u32,
+    },
+    UpdateDeactivated {
+        stake_index: u32,
+    },
+    UpdateLiqPool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Drift {
+    pub counter: Counter,
+    /// value stored in the state
+    pub recorded: u64,
+    /// value of the real account
+    pub actual: u64,
+    pub update: Update,
+}
+
+impl Drift {
+    /// `actual - recorded`
+    pub fn difference(&self) -> i128 {
+        self.actual as i128 - self.recorded as i128
+    }
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: recorded {} actual {} ({:+}). Needs {:?}",
+            self.counter,
+            self.recorded,
+            self.actual,
+            self.difference(),
+            self.update
+        )
+    }
+}
+
+/// Broken invariant. Update instructions will fail or are not enough to fix it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The reserve has less than `available_reserve_balance` + rent exempt reserve
+    ReserveBelowRecorded {
+        recorded: u64,
+        actual: u64,
+    },
+    /// mSOL minted outside of marinade
+    MsolSupplyAboveRecorded {
+        recorded: u64,
+        actual: u64,
+    },
+    /// LP tokens minted outside of marinade
+    LpSupplyAboveRecorded {
+        recorded: u64,
+        actual: u64,
+    },
+    SolLegBelowRentExempt {
+        lamports: u64,
+        rent_exempt: u64,
+    },
+    TicketsExceedControlledLamports {
+        circulating_ticket_balance: u64,
+        total_lamports_under_control: u64,
+    },
+    ActiveBalanceMismatch {
+        total_active_balance: u64,
+        validator_list_sum: u64,
+    },
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::ReserveBelowRecorded { recorded, actual } => write!(
+                f,
+                "Reserve balance {} is less than available_reserve_balance {}",
+                actual, recorded
+            ),
+            Violation::MsolSupplyAboveRecorded { recorded, actual } => write!(
+                f,
+                "mSOL supply {} is more than msol_supply {}",
+                actual, recorded
+            ),
+            Violation::LpSupplyAboveRecorded { recorded, actual } => write!(
+                f,
+                "LP supply {} is more than lp_supply {}",
+                actual, recorded
+            ),
+            Violation::SolLegBelowRentExempt {
+                lamports,
+                rent_exempt,
+            } => write!(
+                f,
+                "SOL leg balance {} is below rent exempt reserve {}",
+                lamports, rent_exempt
+            ),
+            Violation::TicketsExceedControlledLamports {
+                circulating_ticket_balance,
+                total_lamports_under_control,
+            } => write!(
+                f,
+                "Circulating tickets {} exceed total lamports under control {}",
+                circulating_ticket_balance, total_lamports_under_control
+            ),
+            Violation::ActiveBalanceMismatch {
+                total_active_balance,
+                validator_list_sum,
+            } => write!(
+                f,
+                "total_active_balance {} does not match validator list sum {}",
+                total_active_balance, validator_list_sum
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InvariantReport {
+    pub drifts: Vec<Drift>,
+    /// Updates needed even without a drift. A fully deactivated stake keeps its lamports
+    /// out of the reserve until `UpdateDeactivated` withdraws them
+    pub pending_updates: Vec<Update>,
+    pub violations: Vec<Violation>,
+}
+
+impl InvariantReport {
+    pub fn needs_update(&self) -> bool {
+        !self.drifts.is_empty() || !self.pending_updates.is_empty()
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Distinct instructions to run to align all drifted counters and the pending updates
+    pub fn updates(&self) -> Vec<Update> {
+        let mut updates: Vec<Update> = Vec::new();
+        for update in self
+            .drifts
+            .iter()
+            .map(|drift| &drift.update)
+            .chain(&self.pending_updates)
+        {
+            if !updates.contains(update) {
+                updates.push(*update);
+            }
+        }
+        updates
+    }
+
+    fn drift(&mut self, counter: Counter, recorded: u64, actual: u64, update: Update) {
+        if recorded != actual {
+            self.drifts.push(Drift {
+                counter,
+                recorded,
+                actual,
+                update,
+            })
+        }
+    }
+}
+
+pub fn check_invariants(
+    state: &Marinade,
+    balances: &ProtocolBalances,
+) -> Result<InvariantReport, ProgramError> {
+    let mut report = InvariantReport::default();
+
+    // real reserve may be bigger because of direct transfers into it
+    let reserve_balance = balances
+        .reserve_lamports
+        .saturating_sub(state.rent_exempt_for_token_acc);
+    report.drift(
+        Counter::AvailableReserveBalance,
+        state.available_reserve_balance,
+        reserve_balance,
+        Update::AnyStake,
+    );
+    if reserve_balance < state.available_reserve_balance {
+        report.violations.push(Violation::ReserveBelowRecorded {
+            recorded: state.available_reserve_balance,
+            actual: reserve_balance,
+        });
+    }
+
+    // real supplies may be smaller because of burning
+    report.drift(
+        Counter::MsolSupply,
+        state.msol_supply,
+        balances.msol_mint.supply,
+        Update::AnyStake,
+    );
+    if balances.msol_mint.supply > state.msol_supply {
+        report.violations.push(Violation::MsolSupplyAboveRecorded {
+            recorded: state.msol_supply,
+            actual: balances.msol_mint.supply,
+        });
+    }
+    report.drift(
+        Counter::LpSupply,
+        state.liq_pool.lp_supply,
+        balances.lp_mint.supply,
+        Update::UpdateLiqPool,
+    );
+    if balances.lp_mint.supply > state.liq_pool.lp_supply {
+        report.violations.push(Violation::LpSupplyAboveRecorded {
+            recorded: state.liq_pool.lp_supply,
+            actual: balances.lp_mint.supply,
+        });
+    }
+
+    if balances.sol_leg_lamports < state.rent_exempt_for_token_acc {
+        report.violations.push(Violation::SolLegBelowRentExempt {
+            lamports: balances.sol_leg_lamports,
+            rent_exempt: state.rent_exempt_for_token_acc,
+        });
+    }
+
+    for stake_index in 0..state.stake_system.stake_count() {
+        let record: StakeRecord = state
+            .stake_system
+            .get(balances.stake_list_data, stake_index)?;
+        let stake_state = match balances
+            .stake_accounts
+            .iter()
+            .find(|(address, _)| *address == record.stake_account)
+        {
+            Some((_, stake_state)) => stake_state,
+            None => continue,
+        };
+        let delegation = match stake_state.delegation() {
+            Some(delegation) => delegation,
+            None => continue,
+        };
+        let update = if delegation.deactivation_epoch < balances.epoch {
+            report
+                .pending_updates
+                .push(Update::UpdateDeactivated { stake_index });
+            Update::UpdateDeactivated { stake_index }
+        } else if delegation.deactivation_epoch == u64::MAX {
+            Update::UpdateActive { stake_index }
+        } else {
+            // still deactivating: no update recognizes its balance until it is fully deactivated
+            continue;
+        };
+        report.drift(
+            Counter::StakeDelegation {
+                stake_index,
+                stake_account: record.stake_account,
+            },
+            record.last_update_delegated_lamports,
+            delegation.stake,
+            update,
+        );
+    }
+
+    let total_lamports_under_control = state.total_lamports_under_control();
+    if state.circulating_ticket_balance > total_lamports_under_control {
+        report
+            .violations
+            .push(Violation::TicketsExceedControlledLamports {
+                circulating_ticket_balance: state.circulating_ticket_balance,
+                total_lamports_under_control,
+            });
+    }
+
+    let mut validator_list_sum: u64 = 0;
+    for index in 0..state.validator_system.validator_count() {
+        let validator = state
+            .validator_system
+            .get(balances.validator_list_data, index)?;
+        validator_list_sum = validator_list_sum.saturating_add(validator.active_balance);
+    }
+    if validator_list_sum != state.validator_system.total_active_balance {
+        report.violations.push(Violation::ActiveBalanceMismatch {
+            total_active_balance: state.validator_system.total_active_balance,
+            validator_list_sum,
+        });
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{list::List, validator_system::ValidatorRecord};
+    use borsh::BorshSerialize;
+
+    fn list_data<I: BorshSerialize>(discriminator: &[u8; 8], items: &[I]) -> (List, Vec<u8>) {
+        let mut data = discriminator.to_vec();
+        for item in items {
+            item.serialize(&mut data).unwrap();
+        }
+        let item_size = items[0].try_to_vec().unwrap().len() as u32;
+        let list = List {
+            item_size,
+            count: items.len() as u32,
+            ..List::default()
+        };
+        (list, data)
+    }
+
+    fn mint(supply: u64) -> Mint {
+        Mint {
+            supply,
+            is_initialized: true,
+            ..Mint::default()
+        }
+    }
+
+    #[test]
+    fn reports_drifts_and_violations() {
+        let (validator_list, validator_list_data) = list_data(
+            ValidatorRecord::DISCRIMINATOR,
+            &[
+                ValidatorRecord {
+                    active_balance: 100,
+                    ..ValidatorRecord::default()
+                },
+                ValidatorRecord {
+                    active_balance: 50,
+                    ..ValidatorRecord::default()
+                },
+            ],
+        );
+        let stake_account = Pubkey::new_unique();
+        let (stake_list, stake_list_data) = list_data(
+            StakeRecord::DISCRIMINATOR,
+            &[StakeRecord {
+                stake_account,
+                last_update_delegated_lamports: 100,
+                ..StakeRecord::default()
+            }],
+        );
+        let mut state = Marinade {
+            rent_exempt_for_token_acc: 10,
+            available_reserve_balance: 1_000,
+            msol_supply: 500,
+            circulating_ticket_balance: 100,
+            ..Marinade::default()
+        };
+        state.validator_system.validator_list = validator_list;
+        state.validator_system.total_active_balance = 150;
+        state.stake_system.stake_list = stake_list;
+        state.liq_pool.lp_supply = 30;
+
+        let mut stake = solana_program::stake::state::Stake::default();
+        stake.delegation.stake = 120;
+        stake.delegation.deactivation_epoch = u64::MAX;
+        let stake_accounts = [(stake_account, StakeState::Stake(Default::default(), stake))];
+        let msol_mint = mint(400);
+        let lp_mint = mint(30);
+        let mut balances = ProtocolBalances {
+            reserve_lamports: 1_210,
+            msol_mint: &msol_mint,
+            lp_mint: &lp_mint,
+            sol_leg_lamports: 10,
+            validator_list_data: &validator_list_data,
+            stake_list_data: &stake_list_data,
+            stake_accounts: &stake_accounts,
+            epoch: 10,
+        };
+
+        let report = check_invariants(&state, &balances).unwrap();
+        assert!(report.is_healthy(), "{:?}", report.violations);
+        assert_eq!(report.drifts.len(), 3);
+        assert_eq!(report.drifts[0].difference(), 200);
+        assert_eq!(report.drifts[1].difference(), -100);
+        assert_eq!(
+            report.updates(),
+            vec![Update::AnyStake, Update::UpdateActive { stake_index: 0 }]
+        );
+
+        // deactivating stake has nothing to update until the next epoch
+        stake.delegation.deactivation_epoch = 10;
+        let stake_accounts = [(stake_account, StakeState::Stake(Default::default(), stake))];
+        balances.stake_accounts = &stake_accounts;
+        let report = check_invariants(&state, &balances).unwrap();
+        assert_eq!(report.updates(), vec![Update::AnyStake]);
+        balances.epoch = 11;
+        let report = check_invariants(&state, &balances).unwrap();
+        assert_eq!(
+            report.updates(),
+            vec![
+                Update::AnyStake,
+                Update::UpdateDeactivated { stake_index: 0 }
+            ]
+        );
+        // without rewards there is no drift but the lamports still wait for the update
+        stake.delegation.stake = 100;
+        let stake_accounts = [(stake_account, StakeState::Stake(Default::default(), stake))];
+        balances.stake_accounts = &stake_accounts;
+        let report = check_invariants(&state, &balances).unwrap();
+        assert!(report
+            .drifts
+            .iter()
+            .all(|drift| drift.update == Update::AnyStake));
+        assert_eq!(
+            report.pending_updates,
+            vec![Update::UpdateDeactivated { stake_index: 0 }]
+        );
+        assert_eq!(
+            report.updates(),
+            vec![
+                Update::AnyStake,
+                Update::UpdateDeactivated { stake_index: 0 }
+            ]
+        );
+
+        state.validator_system.total_active_balance = 0;
+        state.circulating_ticket_balance = 2_000;
+        balances.reserve_lamports = 500;
+        let report = check_invariants(&state, &balances).unwrap();
+        assert_eq!(
+            report.violations,
+            vec![
+                Violation::ReserveBelowRecorded {
+                    recorded: 1_000,
+                    actual: 490
+                },
+                Violation::TicketsExceedControlledLamports {
+                    circulating_ticket_balance: 2_000,
+                    total_lamports_under_control: 1_000
+                },
+                Violation::ActiveBalanceMismatch {
+                    total_active_balance: 0,
+                    validator_list_sum: 150
+                },
+            ]
+        );
+    }
+}
@@ -1,8 +1,23 @@
+pub mod analytics;
 pub mod calc;
 pub mod checks;
+pub mod constraints;
+pub mod diff;
+pub mod eligibility;
 pub mod error;
+#[cfg(feature = "idl")]
+pub mod idl;
+pub mod index;
+pub mod invariants;
+pub mod list_resize;
 pub mod located;
+#[cfg(feature = "serde")]
+pub mod serde_helpers;
+pub mod simulator;
+pub mod stake_delta;
+pub mod stake_view;
 pub mod state;
+pub mod token;
 pub mod instructions;
 
 use borsh::{BorshDeserialize, BorshSerialize};
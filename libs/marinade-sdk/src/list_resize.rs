@@ -0,0 +1,234 @@
+//! Planning of stake and validator list account migration into a bigger account
+//!
+//! The list is moved with the `new_account` mechanism of `List`: a new account is created,
+//! the list starts changing into it and the items are copied chunk by chunk.
+
+use std::fmt::Display;
+
+use solana_program::{rent::Rent, system_instruction::MAX_PERMITTED_DATA_LENGTH};
+
+use crate::state::{list::List, stake_system::StakeSystem, validator_system::ValidatorSystem};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListKind {
+    Stake,
+    Validator,
+}
+
+impl ListKind {
+    /// Account size for `count` records of the kind
+    pub fn bytes_for(self, count: u32, additional_record_space: u32) -> u32 {
+        match self {
+            ListKind::Stake => StakeSystem::bytes_for_list(count, additional_record_space),
+            ListKind::Validator => ValidatorSystem::bytes_for_list(count, additional_record_space),
+        }
+    }
+
+    pub fn item_size(self, additional_record_space: u32) -> u32 {
+        self.bytes_for(1, additional_record_space) - self.bytes_for(0, additional_record_space)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizeStep {
+    /// Create the new list account owned by the marinade program
+    CreateAccount { space: u32, lamports: u64 },
+    /// Set the list `new_account` (`List::start_changing_account`)
+    StartChange,
+    /// Copy items `from..to` (`List::copy_next_chunk`). The last one switches the list account
+    CopyChunk { from: u32, to: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResizePlan {
+    pub item_size: u32,
+    pub current_capacity: u32,
+    pub new_capacity: u32,
+    pub new_account_size: u32,
+    pub rent_exempt_lamports: u64,
+    /// Empty if the current account already has the capacity
+    pub steps: Vec<ResizeStep>,
+}
+
+impl ResizePlan {
+    pub fn is_needed(&self) -> bool {
+        !self.steps.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizeError {
+    /// Items are copied as is so the record size can not change
+    ItemSizeMismatch {
+        current: u32,
+        requested: u32,
+    },
+    /// The list is already moving into another account
+    AlreadyChanging,
+    /// The target capacity is less than the items in the list
+    TargetBelowCount {
+        target: u32,
+        count: u32,
+    },
+    TooBig {
+        space: u64,
+    },
+    ZeroChunk,
+    /// The current list account can not hold even the list header
+    AccountTooSmall {
+        len: usize,
+    },
+}
+
+impl Display for ResizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResizeError::ItemSizeMismatch { current, requested } => write!(
+                f,
+                "List item size {} can not be changed to {}",
+                current, requested
+            ),
+            ResizeError::AlreadyChanging => write!(f, "List is already changing its account"),
+            ResizeError::TargetBelowCount { target, count } => write!(
+                f,
+                "Target capacity {} is less than list length {}",
+                target, count
+            ),
+            ResizeError::TooBig { space } => write!(
+                f,
+                "Account size {} is over the max {}",
+                space, MAX_PERMITTED_DATA_LENGTH
+            ),
+            ResizeError::ZeroChunk => write!(f, "Chunk size must be positive"),
+            ResizeError::AccountTooSmall { len } => {
+                write!(f, "List account of {} bytes is too small", len)
+            }
+        }
+    }
+}
+
+/// Plans moving `list` (stored in an account of `account_len` bytes) into an account
+/// holding `target_capacity` items, copying at most `items_per_copy` items per step
+pub fn plan_list_resize(
+    kind: ListKind,
+    list: &List,
+    account_len: usize,
+    target_capacity: u32,
+    additional_record_space: u32,
+    items_per_copy: u32,
+    rent: &Rent,
+) -> Result<ResizePlan, ResizeError> {
+    let item_size = kind.item_size(additional_record_space);
+    if item_size != list.item_size() {
+        return Err(ResizeError::ItemSizeMismatch {
+            current: list.item_size(),
+            requested: item_size,
+        });
+    }
+    if list.is_changing_account() {
+        return Err(ResizeError::AlreadyChanging);
+    }
+    if target_capacity < list.len() {
+        return Err(ResizeError::TargetBelowCount {
+            target: target_capacity,
+            count: list.len(),
+        });
+    }
+    if items_per_copy == 0 {
+        return Err(ResizeError::ZeroChunk);
+    }
+    let space = 8 + target_capacity as u64 * item_size as u64;
+    if space > MAX_PERMITTED_DATA_LENGTH {
+        return Err(ResizeError::TooBig { space });
+    }
+
+    if account_len < 8 {
+        return Err(ResizeError::AccountTooSmall { len: account_len });
+    }
+    let current_capacity = list
+        .capacity(account_len)
+        .map_err(|_| ResizeError::TooBig {
+            space: account_len as u64,
+        })?;
+    let new_account_size = kind.bytes_for(target_capacity, additional_record_space);
+    let rent_exempt_lamports = rent.minimum_balance(new_account_size as usize);
+    let mut steps = Vec::new();
+    if current_capacity < target_capacity {
+        steps.push(ResizeStep::CreateAccount {
+            space: new_account_size,
+            lamports: rent_exempt_lamports,
+        });
+        steps.push(ResizeStep::StartChange);
+        let mut from = 0;
+        // an empty list is switched by a single empty copy
+        loop {
+            let to = list.len().min(from + items_per_copy);
+            steps.push(ResizeStep::CopyChunk { from, to });
+            if to == list.len() {
+                break;
+            }
+            from = to;
+        }
+    }
+    Ok(ResizePlan {
+        item_size,
+        current_capacity,
+        new_capacity: List::capacity_of(item_size, new_account_size as usize),
+        new_account_size,
+        rent_exempt_lamports,
+        steps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_chunks() {
+        let item_size = ListKind::Validator.item_size(8);
+        let list = List {
+            item_size,
+            count: 5,
+            ..List::default()
+        };
+        let rent = Rent::default();
+        let account_len = List::bytes_for(item_size, 5) as usize;
+        let plan =
+            plan_list_resize(ListKind::Validator, &list, account_len, 20, 8, 2, &rent).unwrap();
+        assert_eq!(plan.current_capacity, 5);
+        assert_eq!(plan.new_capacity, 20);
+        assert_eq!(
+            plan.new_account_size,
+            ValidatorSystem::bytes_for_list(20, 8)
+        );
+        assert_eq!(
+            plan.steps,
+            vec![
+                ResizeStep::CreateAccount {
+                    space: plan.new_account_size,
+                    lamports: rent.minimum_balance(plan.new_account_size as usize)
+                },
+                ResizeStep::StartChange,
+                ResizeStep::CopyChunk { from: 0, to: 2 },
+                ResizeStep::CopyChunk { from: 2, to: 4 },
+                ResizeStep::CopyChunk { from: 4, to: 5 },
+            ]
+        );
+
+        let plan =
+            plan_list_resize(ListKind::Validator, &list, account_len, 5, 8, 2, &rent).unwrap();
+        assert!(!plan.is_needed());
+        assert_eq!(
+            plan_list_resize(ListKind::Stake, &list, account_len, 20, 8, 2, &rent),
+            Err(ResizeError::ItemSizeMismatch {
+                current: item_size,
+                requested: ListKind::Stake.item_size(8)
+            })
+        );
+        assert_eq!(
+            plan_list_resize(ListKind::Validator, &list, 4, 20, 8, 2, &rent),
+            Err(ResizeError::AccountTooSmall { len: 4 })
+        );
+    }
+}
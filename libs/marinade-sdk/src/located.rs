@@ -1,4 +1,11 @@
-use solana_program::pubkey::Pubkey;
+use borsh::BorshSerialize;
+use micro_anchor::{AccountDeserialize, Discriminator};
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::checks::{check_address, check_owner_program};
 
 /* Parsed account together with location key concept.
  * For example ProgramAccount or CpiAccount from anchor.
@@ -8,3 +15,236 @@ pub trait Located<T> {
     fn as_mut(&mut self) -> &mut T;
     fn key(&self) -> Pubkey;
 }
+
+/// Deserialized account together with its address.
+/// Tracks mutable access so only modified accounts are written back
+#[derive(Clone, Debug)]
+pub struct LocatedAccount<T> {
+    key: Pubkey,
+    inner: T,
+    dirty: bool,
+}
+
+impl<T> LocatedAccount<T> {
+    pub fn new(key: Pubkey, inner: T) -> Self {
+        Self {
+            key,
+            inner,
+            dirty: false,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// `as_mut` was called since loading or the last write back
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+}
+
+impl<T: AccountDeserialize> LocatedAccount<T> {
+    /// Parses raw account data checking the discriminator
+    pub fn from_data(key: Pubkey, mut data: &[u8]) -> Result<Self, ProgramError> {
+        let inner = T::try_deserialize(&mut data).map_err(|e| {
+            msg!("Can not deserialize account {}: {}", key, e);
+            ProgramError::InvalidAccountData
+        })?;
+        Ok(Self::new(key, inner))
+    }
+
+    /// Checks the owner program and the discriminator
+    pub fn from_account_info(account: &AccountInfo) -> Result<Self, ProgramError> {
+        check_owner_program(account, &T::owner(), "located account")?;
+        Self::from_data(*account.key, account.data.borrow().as_ref())
+    }
+}
+
+impl<T: BorshSerialize + Discriminator> LocatedAccount<T> {
+    /// Serializes the account into `account` data if it was modified.
+    /// Bytes after the serialized data are kept untouched
+    pub fn write_back(&mut self, account: &AccountInfo) -> ProgramResult {
+        check_address(account.key, &self.key, "located account")?;
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut serialized = T::DISCRIMINATOR.to_vec();
+        self.inner
+            .serialize(&mut serialized)
+            .map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+        let mut data = account.try_borrow_mut_data()?;
+        if data.len() < serialized.len() {
+            msg!(
+                "Account {} is too small: {} < {}",
+                self.key,
+                data.len(),
+                serialized.len()
+            );
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..serialized.len()].copy_from_slice(&serialized);
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl<T> Located<T> for LocatedAccount<T> {
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+
+    fn as_mut(&mut self) -> &mut T {
+        self.dirty = true;
+        &mut self.inner
+    }
+
+    fn key(&self) -> Pubkey {
+        self.key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::delayed_unstake_ticket::DelayedUnstakeTicket;
+    use micro_anchor::Owner;
+
+    fn ticket_data(lamports_amount: u64) -> Vec<u8> {
+        let mut data = DelayedUnstakeTicket::DISCRIMINATOR.to_vec();
+        DelayedUnstakeTicket {
+            state_address: Pubkey::new_unique(),
+            beneficiary: Pubkey::new_unique(),
+            lamports_amount,
+            created_epoch: 10,
+        }
+        .serialize(&mut data)
+        .unwrap();
+        data
+    }
+
+    #[test]
+    fn bad_discriminator() {
+        let mut data = ticket_data(100);
+        data[0] ^= 1;
+        assert_eq!(
+            LocatedAccount::<DelayedUnstakeTicket>::from_data(Pubkey::new_unique(), &data)
+                .unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+        assert_eq!(
+            LocatedAccount::<DelayedUnstakeTicket>::from_data(Pubkey::new_unique(), &data[..4])
+                .unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn bad_owner() {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = ticket_data(100);
+        let owner = Pubkey::new_unique();
+        let account = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        assert_eq!(
+            LocatedAccount::<DelayedUnstakeTicket>::from_account_info(&account).unwrap_err(),
+            ProgramError::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn write_back_when_dirty() {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        // trailing bytes must survive the write back
+        let mut data = ticket_data(100);
+        data.extend_from_slice(&[7; 4]);
+        let owner = DelayedUnstakeTicket::owner();
+        let account = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+
+        let mut ticket =
+            LocatedAccount::<DelayedUnstakeTicket>::from_account_info(&account).unwrap();
+        assert_eq!(ticket.key(), key);
+        assert!(!ticket.is_dirty());
+        // a clean account is not written
+        account.data.borrow_mut()[8] ^= 1;
+        ticket.write_back(&account).unwrap();
+        assert_ne!(
+            account.data.borrow()[8],
+            ticket.as_ref().state_address.as_ref()[0]
+        );
+        account.data.borrow_mut()[8] ^= 1;
+
+        ticket.as_mut().lamports_amount = 200;
+        assert!(ticket.is_dirty());
+        ticket.write_back(&account).unwrap();
+        assert!(!ticket.is_dirty());
+        let reloaded =
+            LocatedAccount::<DelayedUnstakeTicket>::from_data(key, &account.data.borrow()).unwrap();
+        assert_eq!(reloaded.as_ref().lamports_amount, 200);
+        assert!(account.data.borrow().ends_with(&[7; 4]));
+    }
+
+    #[test]
+    fn write_back_checks_account() {
+        let key = Pubkey::new_unique();
+        let mut ticket =
+            LocatedAccount::<DelayedUnstakeTicket>::from_data(key, &ticket_data(100)).unwrap();
+        ticket.as_mut().lamports_amount = 200;
+        let owner = DelayedUnstakeTicket::owner();
+
+        let other_key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = ticket_data(100);
+        let other = AccountInfo::new(
+            &other_key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        assert_eq!(
+            ticket.write_back(&other).unwrap_err(),
+            ProgramError::InvalidArgument
+        );
+
+        let mut lamports = 0;
+        let mut data = vec![0; 16];
+        let small = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        assert_eq!(
+            ticket.write_back(&small).unwrap_err(),
+            ProgramError::AccountDataTooSmall
+        );
+        assert!(ticket.is_dirty());
+    }
+}
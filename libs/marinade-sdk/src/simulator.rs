@@ -0,0 +1,755 @@
+//! Offline marinade protocol simulator
+//!
+//! Applies user and crank operations to a `Marinade` state using the same accounting
+//! as the program. Token accounts, the reserve and stake accounts are simulated by
+//! plain balances. Stake accounts hold exactly their delegated lamports (no rent),
+//! and the 30 min wait of claim at the epoch start is not simulated.
+
+use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{
+    calc::{proportional, shares_from_value},
+    checks::check_min_amount,
+    error::CommonError,
+    stake_delta::distribute,
+    state::{
+        delayed_unstake_ticket::DelayedUnstakeTicket,
+        marinade::{LiquidUnstakeQuote, Marinade},
+        stake_system::StakeRecord,
+        validator_system::ValidatorRecord,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimStakeStatus {
+    Activating,
+    Active,
+    Deactivating,
+    Inactive,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimStake {
+    pub record: StakeRecord,
+    pub validator_account: Pubkey,
+    pub status: SimStakeStatus,
+    /// Real delegated lamports (including rewards not yet recognized by update)
+    pub lamports: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Simulator {
+    pub state_address: Pubkey,
+    pub state: Marinade,
+    pub epoch: u64,
+    pub validators: Vec<ValidatorRecord>,
+    pub stakes: Vec<SimStake>,
+    /// Index is the ticket id. Claimed tickets are None
+    pub tickets: Vec<Option<DelayedUnstakeTicket>>,
+    pub reserve_lamports: u64,
+    pub sol_leg_lamports: u64,
+    pub msol_leg_balance: u64,
+    pub treasury_msol_balance: u64,
+    next_stake_account: u64,
+}
+
+impl Simulator {
+    /// Starts with real balances matching the virtual ones.
+    /// `state` must not have any stake accounts or validators yet
+    pub fn new(
+        state_address: Pubkey,
+        mut state: Marinade,
+        epoch: u64,
+    ) -> Result<Self, ProgramError> {
+        if state.validator_system.total_active_balance != 0 {
+            msg!("Simulation must start without stakes");
+            return Err(ProgramError::InvalidArgument);
+        }
+        state.stake_system.stake_list.count = 0;
+        state.validator_system.validator_list.count = 0;
+        state.validator_system.total_validator_score = 0;
+        Ok(Self {
+            state_address,
+            reserve_lamports: state.available_reserve_balance + state.rent_exempt_for_token_acc,
+            sol_leg_lamports: state.rent_exempt_for_token_acc,
+            state,
+            epoch,
+            validators: vec![],
+            stakes: vec![],
+            tickets: vec![],
+            msol_leg_balance: 0,
+            treasury_msol_balance: 0,
+            next_stake_account: 0,
+        })
+    }
+
+    pub fn add_validator(&mut self, validator_account: Pubkey, score: u32) {
+        self.validators.push(ValidatorRecord {
+            validator_account,
+            active_balance: 0,
+            score,
+            last_stake_delta_epoch: u64::MAX, // never
+            duplication_flag_bump_seed: 0,
+        });
+        self.state.validator_system.total_validator_score += score;
+        self.state.validator_system.validator_list.count = self.validators.len() as u32;
+    }
+
+    /// SOL leg lamports available for liquid unstake
+    pub fn sol_leg_balance(&self) -> u64 {
+        self.sol_leg_lamports
+            .saturating_sub(self.state.rent_exempt_for_token_acc)
+    }
+
+    /// Returns mSOL received by the user
+    pub fn deposit(&mut self, lamports: u64) -> Result<u64, ProgramError> {
+        check_min_amount(lamports, self.state.min_deposit, "deposit SOL")?;
+        let user_msol_buy_order = self.state.calc_msol_from_lamports(lamports)?;
+        // First we try to "purchase" mSOL from the liq-pool "mSOL leg"
+        let swap_msol_max = user_msol_buy_order.min(self.msol_leg_balance);
+        let mut lamports_to_stake = lamports;
+        if swap_msol_max > 0 {
+            let user_lamports_for_swap = if swap_msol_max == user_msol_buy_order {
+                lamports
+            } else {
+                self.state.calc_lamports_from_msol_amount(swap_msol_max)?
+            };
+            self.sol_leg_lamports += user_lamports_for_swap;
+            self.msol_leg_balance -= swap_msol_max;
+            lamports_to_stake -= user_lamports_for_swap;
+        }
+        let mut msol_out = swap_msol_max;
+        if lamports_to_stake > 0 {
+            self.state.check_staking_cap(lamports_to_stake)?;
+            let msol_to_mint = self.state.calc_msol_from_lamports(lamports_to_stake)?;
+            self.reserve_lamports += lamports_to_stake;
+            self.state.on_transfer_to_reserve(lamports_to_stake);
+            self.state.on_msol_mint(msol_to_mint);
+            msol_out += msol_to_mint;
+        }
+        self.update_msol_price()?;
+        Ok(msol_out)
+    }
+
+    pub fn liquid_unstake(&mut self, msol_amount: u64) -> Result<LiquidUnstakeQuote, ProgramError> {
+        let quote = self
+            .state
+            .liquid_unstake_quote(msol_amount, self.sol_leg_balance())?;
+        self.sol_leg_lamports -= quote.lamports_out;
+        self.treasury_msol_balance += quote.treasury_msol_cut;
+        self.msol_leg_balance += quote.msol_amount - quote.treasury_msol_cut;
+        Ok(quote)
+    }
+
+    /// Returns LP tokens minted to the user
+    pub fn add_liquidity(&mut self, lamports: u64) -> Result<u64, ProgramError> {
+        check_min_amount(lamports, self.state.min_deposit, "add_liquidity")?;
+        let msol_leg_value = self
+            .state
+            .calc_lamports_from_msol_amount(self.msol_leg_balance)?;
+        let total_liq_pool_value = self.sol_leg_balance() + msol_leg_value;
+        self.state
+            .liq_pool
+            .check_liquidity_cap(lamports, total_liq_pool_value)?;
+        let shares_for_user = shares_from_value(
+            lamports,
+            total_liq_pool_value,
+            self.state.liq_pool.lp_supply,
+        )?;
+        self.sol_leg_lamports += lamports;
+        self.state.liq_pool.on_lp_mint(shares_for_user);
+        Ok(shares_for_user)
+    }
+
+    /// Returns (lamports, mSOL) received by the user
+    pub fn remove_liquidity(&mut self, tokens: u64) -> Result<(u64, u64), ProgramError> {
+        let lp_supply = self.state.liq_pool.lp_supply;
+        let user_remove_lamports = proportional(tokens, self.sol_leg_balance(), lp_supply)?;
+        let user_remove_msol = proportional(tokens, self.msol_leg_balance, lp_supply)?;
+        check_min_amount(
+            user_remove_lamports
+                + self
+                    .state
+                    .calc_lamports_from_msol_amount(user_remove_msol)?,
+            self.state.min_withdraw,
+            "removed liquidity",
+        )?;
+        self.state.liq_pool.on_lp_burn(tokens)?;
+        self.sol_leg_lamports -= user_remove_lamports;
+        self.msol_leg_balance -= user_remove_msol;
+        Ok((user_remove_lamports, user_remove_msol))
+    }
+
+    /// Returns the ticket id
+    pub fn order_unstake(
+        &mut self,
+        msol_amount: u64,
+        beneficiary: Pubkey,
+    ) -> Result<usize, ProgramError> {
+        let lamports_amount = self.state.calc_lamports_from_msol_amount(msol_amount)?;
+        check_min_amount(lamports_amount, self.state.min_withdraw, "withdraw SOL")?;
+        self.state.on_msol_burn(msol_amount)?;
+        self.state.circulating_ticket_count += 1;
+        self.state.circulating_ticket_balance += lamports_amount;
+        // after stake-delta of this epoch the ticket must wait one more epoch
+        let created_epoch = if self.epoch == self.state.stake_system.last_stake_delta_epoch {
+            self.epoch + 1
+        } else {
+            self.epoch
+        };
+        self.tickets.push(Some(DelayedUnstakeTicket {
+            state_address: self.state_address,
+            beneficiary,
+            lamports_amount,
+            created_epoch,
+        }));
+        Ok(self.tickets.len() - 1)
+    }
+
+    /// Returns lamports paid to the beneficiary
+    pub fn claim(&mut self, ticket: usize) -> Result<u64, ProgramError> {
+        let lamports = match self.tickets.get(ticket) {
+            Some(Some(ticket)) => {
+                if self.epoch < ticket.created_epoch + 1 {
+                    msg!(
+                        "Ticket not due. Wait at least one epoch change since {}",
+                        ticket.created_epoch
+                    );
+                    return Err(CommonError::TicketNotDue.into());
+                }
+                ticket.lamports_amount
+            }
+            _ => {
+                msg!("Unknown or claimed ticket {}", ticket);
+                return Err(ProgramError::InvalidArgument);
+            }
+        };
+        let available = self
+            .reserve_lamports
+            .checked_sub(self.state.rent_exempt_for_token_acc)
+            .ok_or(CommonError::CalculationFailure)?;
+        if lamports > available {
+            msg!("Need to wait for stake accounts to be deactivated");
+            return Err(ProgramError::InsufficientFunds);
+        }
+        self.state.circulating_ticket_balance -= lamports;
+        self.state.circulating_ticket_count -= 1;
+        self.state.on_transfer_from_reserve(lamports)?;
+        self.reserve_lamports -= lamports;
+        self.tickets[ticket] = None;
+        Ok(lamports)
+    }
+
+    /// Stakes or unstakes `Marinade::stake_delta` distributing it by validator stake targets.
+    /// Stakes must be updated first
+    pub fn stake_delta(&mut self) -> Result<i128, ProgramError> {
+        let stake_delta = self.state.stake_delta(self.reserve_lamports);
+        if stake_delta > 0 {
+            let amount = stake_delta as u64;
+            if amount >= self.state.stake_system.min_stake {
+                self.stake(amount)?;
+            }
+        } else if stake_delta < 0 {
+            self.unstake((-stake_delta) as u64)?;
+        }
+        self.state.stake_system.last_stake_delta_epoch = self.epoch;
+        Ok(stake_delta)
+    }
+
+    /// Moves to the next epoch. `rewards` gives the lamports earned during the epoch by a stake.
+    /// Nothing changes if it fails for any stake
+    pub fn advance_epoch<F: Fn(&SimStake) -> Result<u64, ProgramError>>(
+        &mut self,
+        rewards: F,
+    ) -> Result<(), ProgramError> {
+        let lamports = self
+            .stakes
+            .iter()
+            .map(|stake| {
+                if matches!(
+                    stake.status,
+                    SimStakeStatus::Active | SimStakeStatus::Deactivating
+                ) {
+                    stake
+                        .lamports
+                        .checked_add(rewards(stake)?)
+                        .ok_or_else(|| CommonError::CalculationFailure.into())
+                } else {
+                    Ok(stake.lamports)
+                }
+            })
+            .collect::<Result<Vec<u64>, ProgramError>>()?;
+        for (stake, lamports) in self.stakes.iter_mut().zip(lamports) {
+            stake.lamports = lamports;
+            stake.status = match stake.status {
+                SimStakeStatus::Activating | SimStakeStatus::Active => SimStakeStatus::Active,
+                SimStakeStatus::Deactivating | SimStakeStatus::Inactive => SimStakeStatus::Inactive,
+            };
+        }
+        self.epoch += 1;
+        Ok(())
+    }
+
+    /// advance_epoch with the same reward rate for every stake
+    pub fn advance_epoch_with_rate(
+        &mut self,
+        numerator: u64,
+        denominator: u64,
+    ) -> Result<(), ProgramError> {
+        self.advance_epoch(|stake| Ok(proportional(stake.lamports, numerator, denominator)?))
+    }
+
+    /// Recognizes rewards (or slashing) of an active stake
+    pub fn update_active(&mut self, stake_index: usize) -> Result<(), ProgramError> {
+        let stake = self.stakes[stake_index].clone();
+        if !matches!(
+            stake.status,
+            SimStakeStatus::Activating | SimStakeStatus::Active
+        ) {
+            msg!("Stake {} is not active", stake.record.stake_account);
+            return Err(ProgramError::InvalidArgument);
+        }
+        let last_update_lamports = stake.record.last_update_delegated_lamports;
+        if stake.lamports >= last_update_lamports {
+            let rewards = stake.lamports - last_update_lamports;
+            self.mint_reward_fee(rewards)?;
+            self.validator_mut(&stake.validator_account)?.active_balance += rewards;
+            self.state.validator_system.total_active_balance += rewards;
+        } else {
+            let slashed = last_update_lamports - stake.lamports;
+            let validator = self.validator_mut(&stake.validator_account)?;
+            validator.active_balance = validator
+                .active_balance
+                .checked_sub(slashed)
+                .ok_or(CommonError::CalculationFailure)?;
+            let validator_system = &mut self.state.validator_system;
+            validator_system.total_active_balance = validator_system
+                .total_active_balance
+                .checked_sub(slashed)
+                .ok_or(CommonError::CalculationFailure)?;
+        }
+        let record = &mut self.stakes[stake_index].record;
+        record.last_update_delegated_lamports = stake.lamports;
+        record.last_update_epoch = self.epoch;
+        self.update_msol_price()
+    }
+
+    /// Withdraws a fully deactivated stake into the reserve and removes it from the list
+    pub fn update_deactivated(&mut self, stake_index: usize) -> Result<(), ProgramError> {
+        let stake = self.stakes[stake_index].clone();
+        if stake.status != SimStakeStatus::Inactive {
+            msg!("Stake {} is not deactivated", stake.record.stake_account);
+            return Err(ProgramError::InvalidArgument);
+        }
+        let last_update_lamports = stake.record.last_update_delegated_lamports;
+        self.mint_reward_fee(stake.lamports.saturating_sub(last_update_lamports))?;
+        let cooling_down = if stake.record.is_emergency_unstaking == 0 {
+            &mut self.state.stake_system.delayed_unstake_cooling_down
+        } else {
+            &mut self.state.emergency_cooling_down
+        };
+        *cooling_down = cooling_down
+            .checked_sub(last_update_lamports)
+            .ok_or(CommonError::CalculationFailure)?;
+        self.reserve_lamports += stake.lamports;
+        self.state.on_transfer_to_reserve(stake.lamports);
+        self.stakes.swap_remove(stake_index);
+        self.state.stake_system.stake_list.count = self.stakes.len() as u32;
+        self.update_msol_price()
+    }
+
+    /// update_active for all not updated stakes in this epoch and update_deactivated for all inactive ones
+    pub fn update_all(&mut self) -> Result<(), ProgramError> {
+        for index in (0..self.stakes.len()).rev() {
+            let stake = &self.stakes[index];
+            match stake.status {
+                SimStakeStatus::Activating | SimStakeStatus::Active => {
+                    if stake.record.last_update_epoch < self.epoch {
+                        self.update_active(index)?
+                    }
+                }
+                SimStakeStatus::Inactive => self.update_deactivated(index)?,
+                SimStakeStatus::Deactivating => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn stake(&mut self, amount: u64) -> Result<(), ProgramError> {
+        let total_stake_target = self.state.validator_system.total_active_balance + amount;
+        let under_target = self
+            .validators
+            .iter()
+            .map(|validator| {
+                Ok(self
+                    .state
+                    .validator_system
+                    .validator_stake_target(validator, total_stake_target)?
+                    .saturating_sub(validator.active_balance))
+            })
+            .collect::<Result<Vec<u64>, CommonError>>()?;
+        let caps = vec![u64::MAX; self.validators.len()];
+        for (validator_index, lamports) in Self::distribute(amount, &under_target, &caps)? {
+            let validator = &mut self.validators[validator_index];
+            validator.active_balance += lamports;
+            validator.last_stake_delta_epoch = self.epoch;
+            let validator_account = validator.validator_account;
+            self.new_stake(validator_account, lamports, SimStakeStatus::Activating);
+            self.state.validator_system.total_active_balance += lamports;
+            self.state.on_transfer_from_reserve(lamports)?;
+            self.reserve_lamports -= lamports;
+        }
+        Ok(())
+    }
+
+    fn unstake(&mut self, amount: u64) -> Result<(), ProgramError> {
+        let total_stake_target = self
+            .state
+            .validator_system
+            .total_active_balance
+            .saturating_sub(amount);
+        let over_target = self
+            .validators
+            .iter()
+            .map(|validator| {
+                Ok(validator.active_balance.saturating_sub(
+                    self.state
+                        .validator_system
+                        .validator_stake_target(validator, total_stake_target)?,
+                ))
+            })
+            .collect::<Result<Vec<u64>, CommonError>>()?;
+        let caps: Vec<u64> = self
+            .validators
+            .iter()
+            .map(|validator| validator.active_balance)
+            .collect();
+        for (validator_index, lamports) in Self::distribute(amount, &over_target, &caps)? {
+            let validator_account = self.validators[validator_index].validator_account;
+            self.deactivate(&validator_account, lamports)?;
+            let validator = &mut self.validators[validator_index];
+            validator.active_balance -= lamports;
+            validator.last_stake_delta_epoch = self.epoch;
+            self.state.validator_system.total_active_balance -= lamports;
+            self.state.stake_system.delayed_unstake_cooling_down += lamports;
+        }
+        Ok(())
+    }
+
+    /// Deactivates stakes of the validator, splitting the last one if needed
+    fn deactivate(
+        &mut self,
+        validator_account: &Pubkey,
+        mut lamports: u64,
+    ) -> Result<(), ProgramError> {
+        for index in 0..self.stakes.len() {
+            if lamports == 0 {
+                break;
+            }
+            let stake = &mut self.stakes[index];
+            if stake.validator_account != *validator_account
+                || !matches!(
+                    stake.status,
+                    SimStakeStatus::Activating | SimStakeStatus::Active
+                )
+            {
+                continue;
+            }
+            if stake.lamports != stake.record.last_update_delegated_lamports {
+                msg!(
+                    "Stake {} must be updated before unstaking",
+                    stake.record.stake_account
+                );
+                return Err(CommonError::StakeAccountNotUpdatedYet.into());
+            }
+            if stake.lamports <= lamports {
+                stake.status = SimStakeStatus::Deactivating;
+                lamports -= stake.lamports;
+            } else {
+                stake.lamports -= lamports;
+                stake.record.last_update_delegated_lamports -= lamports;
+                self.new_stake(*validator_account, lamports, SimStakeStatus::Deactivating);
+                lamports = 0;
+            }
+        }
+        if lamports > 0 {
+            msg!(
+                "Validator {} has not enough stake to deactivate",
+                validator_account
+            );
+            return Err(CommonError::CalculationFailure.into());
+        }
+        Ok(())
+    }
+
+    /// Parts of `amount` by validator index
+    fn distribute(
+        amount: u64,
+        gaps: &[u64],
+        caps: &[u64],
+    ) -> Result<impl Iterator<Item = (usize, u64)>, ProgramError> {
+        let (parts, remaining) = distribute(amount, gaps, caps);
+        if remaining > 0 {
+            msg!("No validator to move {} lamports", remaining);
+            return Err(ProgramError::InvalidArgument);
+        }
+        Ok(parts
+            .into_iter()
+            .enumerate()
+            .filter(|(_, lamports)| *lamports > 0))
+    }
+
+    fn new_stake(&mut self, validator_account: Pubkey, lamports: u64, status: SimStakeStatus) {
+        let mut stake_account = [0u8; 32];
+        stake_account[..8].copy_from_slice(&self.next_stake_account.to_le_bytes());
+        self.next_stake_account += 1;
+        self.stakes.push(SimStake {
+            record: StakeRecord {
+                stake_account: Pubkey::new_from_array(stake_account),
+                last_update_delegated_lamports: lamports,
+                last_update_epoch: self.epoch,
+                is_emergency_unstaking: 0,
+            },
+            validator_account,
+            status,
+            lamports,
+        });
+        self.state.stake_system.stake_list.count = self.stakes.len() as u32;
+    }
+
+    fn validator_mut(
+        &mut self,
+        validator_account: &Pubkey,
+    ) -> Result<&mut ValidatorRecord, ProgramError> {
+        self.validators
+            .iter_mut()
+            .find(|validator| validator.validator_account == *validator_account)
+            .ok_or(CommonError::InvalidValidator.into())
+    }
+
+    /// fees are computed as msol amount with the current price (rewards not yet taken into account)
+    fn mint_reward_fee(&mut self, rewards: u64) -> Result<(), ProgramError> {
+        let msol_fees = self.state.reward_fee_msol(rewards)?;
+        if msol_fees > 0 {
+            self.treasury_msol_balance += msol_fees;
+            self.state.on_msol_mint(msol_fees);
+        }
+        Ok(())
+    }
+
+    fn update_msol_price(&mut self) -> Result<(), ProgramError> {
+        self.state.msol_price = self
+            .state
+            .calc_lamports_from_msol_amount(Marinade::PRICE_DENOMINATOR)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{fee::Fee, liq_pool::LiqPool, stake_system::StakeSystem};
+
+    const RENT: u64 = 1_000;
+
+    fn simulator(validators: &[Pubkey]) -> Simulator {
+        let state = Marinade {
+            rent_exempt_for_token_acc: RENT,
+            reward_fee: Fee::from_basis_points(1_000),
+            stake_system: StakeSystem {
+                min_stake: 1_000,
+                ..Default::default()
+            },
+            liq_pool: LiqPool {
+                lp_liquidity_target: 1_000_000,
+                lp_max_fee: Fee::from_basis_points(300),
+                lp_min_fee: Fee::from_basis_points(30),
+                treasury_cut: Fee::from_basis_points(2_500),
+                liquidity_sol_cap: u64::MAX,
+                ..Default::default()
+            },
+            msol_price: Marinade::PRICE_DENOMINATOR,
+            min_deposit: 1,
+            min_withdraw: 1,
+            staking_sol_cap: u64::MAX,
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(Pubkey::new_unique(), state, 10).unwrap();
+        for validator in validators {
+            simulator.add_validator(*validator, 1);
+        }
+        simulator
+    }
+
+    #[test]
+    fn starts_without_stakes() {
+        let mut state = Marinade::default();
+        state.validator_system.total_active_balance = 1;
+        assert_eq!(
+            Simulator::new(Pubkey::new_unique(), state, 0).unwrap_err(),
+            ProgramError::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn stake_rewards_and_delayed_unstake() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut sim = simulator(&[a, b]);
+        assert_eq!(sim.deposit(1_000_000).unwrap(), 1_000_000);
+        assert_eq!(sim.reserve_lamports, RENT + 1_000_000);
+
+        assert_eq!(sim.stake_delta().unwrap(), 1_000_000);
+        assert_eq!(sim.reserve_lamports, RENT);
+        assert_eq!(sim.state.available_reserve_balance, 0);
+        assert_eq!(sim.state.validator_system.total_active_balance, 1_000_000);
+        assert_eq!(sim.validators[0].active_balance, 500_000);
+        assert_eq!(sim.validators[1].active_balance, 500_000);
+        assert_eq!(sim.stakes.len(), 2);
+        assert!(sim
+            .stakes
+            .iter()
+            .all(|stake| stake.status == SimStakeStatus::Activating));
+
+        // activating stakes earn nothing
+        sim.advance_epoch(|_| Ok(10_000)).unwrap();
+        sim.advance_epoch(|stake| {
+            Ok(if stake.validator_account == a {
+                10_000
+            } else {
+                0
+            })
+        })
+        .unwrap();
+        assert_eq!(sim.epoch, 12);
+        sim.update_all().unwrap();
+        // 10% fee minted at price 1
+        assert_eq!(sim.treasury_msol_balance, 1_000);
+        assert_eq!(sim.state.msol_supply, 1_001_000);
+        assert_eq!(sim.state.validator_system.total_active_balance, 1_010_000);
+        assert_eq!(sim.validators[0].active_balance, 510_000);
+        assert_eq!(
+            sim.state.msol_price,
+            proportional(Marinade::PRICE_DENOMINATOR, 1_010_000, 1_001_000).unwrap()
+        );
+
+        let beneficiary = Pubkey::new_unique();
+        let ticket = sim.order_unstake(100_100, beneficiary).unwrap();
+        assert_eq!(sim.state.circulating_ticket_balance, 101_000);
+        assert_eq!(sim.state.msol_supply, 900_900);
+        assert_eq!(
+            sim.claim(ticket).unwrap_err(),
+            CommonError::TicketNotDue.into()
+        );
+
+        assert_eq!(sim.stake_delta().unwrap(), -101_000);
+        // both validators are moved to the target of 454_500
+        assert_eq!(sim.validators[0].active_balance, 454_500);
+        assert_eq!(sim.validators[1].active_balance, 454_500);
+        assert_eq!(sim.state.stake_system.delayed_unstake_cooling_down, 101_000);
+        assert_eq!(
+            sim.stakes
+                .iter()
+                .filter(|stake| stake.status == SimStakeStatus::Deactivating)
+                .map(|stake| stake.lamports)
+                .collect::<Vec<u64>>(),
+            vec![55_500, 45_500]
+        );
+
+        sim.advance_epoch(|_| Ok(0)).unwrap();
+        assert_eq!(
+            sim.claim(ticket).unwrap_err(),
+            ProgramError::InsufficientFunds
+        );
+        sim.update_all().unwrap();
+        assert_eq!(sim.stakes.len(), 2);
+        assert_eq!(sim.state.stake_system.delayed_unstake_cooling_down, 0);
+        assert_eq!(sim.reserve_lamports, RENT + 101_000);
+
+        assert_eq!(sim.claim(ticket).unwrap(), 101_000);
+        assert_eq!(sim.reserve_lamports, RENT);
+        assert_eq!(sim.state.circulating_ticket_balance, 0);
+        assert_eq!(sim.state.circulating_ticket_count, 0);
+        assert!(sim.claim(ticket).is_err());
+    }
+
+    #[test]
+    fn liquid_unstake_and_liquidity() {
+        let mut sim = simulator(&[]);
+        sim.deposit(1_000_000).unwrap();
+        assert_eq!(sim.add_liquidity(100_000).unwrap(), 100_000);
+        assert_eq!(sim.sol_leg_balance(), 100_000);
+
+        let expected = sim.state.liquid_unstake_quote(10_000, 100_000).unwrap();
+        let quote = sim.liquid_unstake(10_000).unwrap();
+        assert_eq!(quote, expected);
+        assert!(quote.treasury_msol_cut > 0);
+        assert_eq!(sim.sol_leg_balance(), 100_000 - quote.lamports_out);
+        assert_eq!(sim.treasury_msol_balance, quote.treasury_msol_cut);
+        assert_eq!(sim.msol_leg_balance, 10_000 - quote.treasury_msol_cut);
+
+        // a deposit buys mSOL from the mSOL leg first without minting
+        let msol_leg_balance = sim.msol_leg_balance;
+        assert_eq!(sim.deposit(5_000).unwrap(), 5_000);
+        assert_eq!(sim.msol_leg_balance, msol_leg_balance - 5_000);
+        assert_eq!(sim.sol_leg_balance(), 105_000 - quote.lamports_out);
+        assert_eq!(sim.state.msol_supply, 1_000_000);
+        assert_eq!(sim.reserve_lamports, RENT + 1_000_000);
+
+        let (lamports, msol) = sim.remove_liquidity(100_000).unwrap();
+        assert_eq!(lamports, 105_000 - quote.lamports_out);
+        assert_eq!(msol, msol_leg_balance - 5_000);
+        assert_eq!(sim.state.liq_pool.lp_supply, 0);
+        assert_eq!((sim.sol_leg_balance(), sim.msol_leg_balance), (0, 0));
+    }
+
+    #[test]
+    fn slashing() {
+        let validator = Pubkey::new_unique();
+        let mut sim = simulator(&[validator]);
+        sim.deposit(1_000_000).unwrap();
+        sim.stake_delta().unwrap();
+        sim.advance_epoch(|_| Ok(0)).unwrap();
+        sim.stakes[0].lamports -= 100_000;
+        sim.update_active(0).unwrap();
+        assert_eq!(sim.stakes[0].record.last_update_delegated_lamports, 900_000);
+        assert_eq!(sim.validators[0].active_balance, 900_000);
+        assert_eq!(sim.state.validator_system.total_active_balance, 900_000);
+        assert_eq!(sim.treasury_msol_balance, 0);
+        assert_eq!(
+            sim.state.msol_price,
+            proportional(Marinade::PRICE_DENOMINATOR, 900_000, 1_000_000).unwrap()
+        );
+
+        // inconsistent balances fail instead of overflowing
+        sim.validators[0].active_balance = 10;
+        sim.stakes[0].lamports -= 100;
+        assert_eq!(
+            sim.update_active(0).unwrap_err(),
+            CommonError::CalculationFailure.into()
+        );
+    }
+
+    #[test]
+    fn advance_epoch_errors() {
+        let validator = Pubkey::new_unique();
+        let mut sim = simulator(&[validator]);
+        sim.deposit(1_000_000).unwrap();
+        sim.stake_delta().unwrap();
+        sim.advance_epoch_with_rate(1, 100).unwrap();
+        assert_eq!(sim.stakes[0].lamports, 1_000_000);
+        sim.advance_epoch_with_rate(1, 100).unwrap();
+        assert_eq!(sim.stakes[0].lamports, 1_010_000);
+
+        let epoch = sim.epoch;
+        assert_eq!(
+            sim.advance_epoch_with_rate(1, 0).unwrap_err(),
+            CommonError::CalculationFailure.into()
+        );
+        assert_eq!(
+            sim.advance_epoch(|_| Ok(u64::MAX)).unwrap_err(),
+            CommonError::CalculationFailure.into()
+        );
+        assert_eq!(sim.epoch, epoch);
+        assert_eq!(sim.stakes[0].lamports, 1_010_000);
+    }
+}
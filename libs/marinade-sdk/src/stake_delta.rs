@@ -0,0 +1,229 @@
+//! Breakdown of `Marinade::stake_delta` for crank operators
+
+use std::cmp::Reverse;
+
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+use crate::state::marinade::Marinade;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValidatorStakeDelta {
+    pub index: u32,
+    pub validator_account: Pubkey,
+    pub score: u32,
+    pub active_balance: u64,
+    /// `validator_stake_target` for `StakeDeltaReport::total_stake_target`
+    pub stake_target: u64,
+    /// Positive to stake, negative to unstake
+    pub planned: i128,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StakeDeltaReport {
+    pub reserve_balance: u64,
+    pub rent_exempt_for_token_acc: u64,
+    pub delayed_unstake_cooling_down: u64,
+    pub circulating_ticket_balance: u64,
+    pub emergency_cooling_down: u64,
+    /// `Marinade::stake_delta_without_emergency`
+    pub without_emergency: i128,
+    /// `without_emergency` was negative so emergency_cooling_down was counted (and the result clamped to 0)
+    pub emergency_branch: bool,
+    /// Positive to stake, negative to unstake
+    pub stake_delta: i128,
+    /// A positive stake_delta below `min_stake` is not staked
+    pub below_min_stake: bool,
+    /// total_active_balance moved by stake_delta
+    pub total_stake_target: u64,
+    /// Validators with planned stake or unstake, biggest first
+    pub validators: Vec<ValidatorStakeDelta>,
+    /// Part of stake_delta no validator can take
+    pub undistributed: u64,
+}
+
+impl StakeDeltaReport {
+    pub fn is_stake(&self) -> bool {
+        self.stake_delta > 0 && !self.below_min_stake
+    }
+
+    pub fn is_unstake(&self) -> bool {
+        self.stake_delta < 0
+    }
+}
+
+/// Splits `amount` filling bigger `gaps` first and then bigger remaining `caps`.
+/// Returns the part of every index and the amount over all the caps
+pub fn distribute(amount: u64, gaps: &[u64], caps: &[u64]) -> (Vec<u64>, u64) {
+    let mut parts = vec![0u64; caps.len()];
+    let mut remaining = amount;
+    let mut order: Vec<usize> = (0..caps.len()).collect();
+    order.sort_by_key(|index| Reverse(gaps[*index].min(caps[*index])));
+    for index in order.iter() {
+        let part = remaining.min(gaps[*index]).min(caps[*index]);
+        parts[*index] += part;
+        remaining -= part;
+    }
+    order.sort_by_key(|index| Reverse(caps[*index] - parts[*index]));
+    for index in order {
+        let part = remaining.min(caps[index] - parts[index]);
+        parts[index] += part;
+        remaining -= part;
+    }
+    (parts, remaining)
+}
+
+/// Explains `state.stake_delta(reserve_balance)` and distributes it by validator stake targets
+pub fn stake_delta_report(
+    state: &Marinade,
+    reserve_balance: u64,
+    validator_list_data: &[u8],
+) -> Result<StakeDeltaReport, ProgramError> {
+    let without_emergency = state.stake_delta_without_emergency(reserve_balance);
+    let stake_delta = state.stake_delta(reserve_balance);
+    let below_min_stake = stake_delta > 0 && (stake_delta as u64) < state.stake_system.min_stake;
+    let validator_system = &state.validator_system;
+    let amount = if below_min_stake {
+        0
+    } else {
+        stake_delta.unsigned_abs() as u64
+    };
+    let total_stake_target = if stake_delta >= 0 {
+        validator_system.total_active_balance.saturating_add(amount)
+    } else {
+        validator_system.total_active_balance.saturating_sub(amount)
+    };
+
+    let mut validators = Vec::new();
+    for index in 0..validator_system.validator_count() {
+        let validator = validator_system.get(validator_list_data, index)?;
+        let stake_target =
+            validator_system.validator_stake_target(&validator, total_stake_target)?;
+        validators.push(ValidatorStakeDelta {
+            index,
+            validator_account: validator.validator_account,
+            score: validator.score,
+            active_balance: validator.active_balance,
+            stake_target,
+            planned: 0,
+        });
+    }
+    // the most under (over) target validators are staked (unstaked) first
+    let gaps: Vec<u64> = validators
+        .iter()
+        .map(|validator| {
+            if stake_delta >= 0 {
+                validator
+                    .stake_target
+                    .saturating_sub(validator.active_balance)
+            } else {
+                validator
+                    .active_balance
+                    .saturating_sub(validator.stake_target)
+            }
+        })
+        .collect();
+    // a validator can not unstake more than its active balance
+    let caps: Vec<u64> = validators
+        .iter()
+        .map(|validator| {
+            if stake_delta >= 0 {
+                u64::MAX
+            } else {
+                validator.active_balance
+            }
+        })
+        .collect();
+    let (parts, remaining) = distribute(amount, &gaps, &caps);
+    for (validator, part) in validators.iter_mut().zip(parts) {
+        validator.planned = if stake_delta >= 0 {
+            part as i128
+        } else {
+            -(part as i128)
+        };
+    }
+    validators.sort_by_key(|validator| Reverse(validator.planned.unsigned_abs()));
+    validators.retain(|validator| validator.planned != 0);
+
+    Ok(StakeDeltaReport {
+        reserve_balance,
+        rent_exempt_for_token_acc: state.rent_exempt_for_token_acc,
+        delayed_unstake_cooling_down: state.stake_system.delayed_unstake_cooling_down,
+        circulating_ticket_balance: state.circulating_ticket_balance,
+        emergency_cooling_down: state.emergency_cooling_down,
+        without_emergency,
+        emergency_branch: without_emergency < 0,
+        stake_delta,
+        below_min_stake,
+        total_stake_target,
+        validators,
+        undistributed: remaining,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{list::List, validator_system::ValidatorRecord};
+    use borsh::BorshSerialize;
+
+    fn state_with_validators(validators: &[(u32, u64)]) -> (Marinade, Vec<u8>) {
+        let mut data = ValidatorRecord::DISCRIMINATOR.to_vec();
+        let mut state = Marinade {
+            rent_exempt_for_token_acc: 10,
+            ..Marinade::default()
+        };
+        for (score, active_balance) in validators {
+            ValidatorRecord {
+                validator_account: Pubkey::new_unique(),
+                score: *score,
+                active_balance: *active_balance,
+                ..ValidatorRecord::default()
+            }
+            .serialize(&mut data)
+            .unwrap();
+            state.validator_system.total_validator_score += score;
+            state.validator_system.total_active_balance += active_balance;
+        }
+        state.validator_system.validator_list = List {
+            item_size: ValidatorRecord::default().try_to_vec().unwrap().len() as u32,
+            count: validators.len() as u32,
+            ..List::default()
+        };
+        (state, data)
+    }
+
+    #[test]
+    fn stake_distribution() {
+        let (mut state, data) = state_with_validators(&[(1, 100), (1, 0), (2, 300)]);
+        state.circulating_ticket_balance = 50;
+        state.stake_system.delayed_unstake_cooling_down = 30;
+        let report = stake_delta_report(&state, 230, &data).unwrap();
+        assert_eq!(report.without_emergency, 200);
+        assert!(!report.emergency_branch);
+        assert_eq!(report.stake_delta, 200);
+        assert_eq!(report.total_stake_target, 600);
+        let planned: Vec<(u32, i128)> = report
+            .validators
+            .iter()
+            .map(|validator| (validator.index, validator.planned))
+            .collect();
+        assert_eq!(planned, vec![(1, 150), (0, 50)]);
+        assert_eq!(report.undistributed, 0);
+    }
+
+    #[test]
+    fn emergency_unstake() {
+        let (mut state, data) = state_with_validators(&[(1, 500), (1, 100)]);
+        state.circulating_ticket_balance = 300;
+        state.emergency_cooling_down = 100;
+        let report = stake_delta_report(&state, 110, &data).unwrap();
+        assert_eq!(report.without_emergency, -200);
+        assert!(report.emergency_branch);
+        assert_eq!(report.stake_delta, -100);
+        assert!(report.is_unstake());
+        assert_eq!(report.total_stake_target, 500);
+        assert_eq!(report.validators.len(), 1);
+        assert_eq!(report.validators[0].index, 0);
+        assert_eq!(report.validators[0].planned, -100);
+    }
+}
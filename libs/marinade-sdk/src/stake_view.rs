@@ -0,0 +1,276 @@
+//! Stake list records joined with the fetched stake accounts for monitoring
+
+use std::collections::HashMap;
+
+use solana_program::{
+    clock::Clock, program_error::ProgramError, pubkey::Pubkey, stake::state::StakeState,
+};
+
+use crate::state::stake_system::{StakeRecord, StakeSystem};
+
+/// Stake activation is judged by epochs only (warmup and cooldown rate limits are ignored)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StakeStatus {
+    Activating,
+    Active,
+    /// Active but not updated in this epoch. Needs `update_active`
+    OutOfDate,
+    Deactivating,
+    /// Fully deactivated. Needs `update_deactivated`
+    Inactive,
+    /// Deactivating by emergency unstake
+    EmergencyUnstaking,
+    /// The stake account was not fetched or is not delegated
+    Missing,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JoinedStake {
+    pub index: u32,
+    pub record: StakeRecord,
+    pub status: StakeStatus,
+    /// `None` if the stake is missing
+    pub validator_account: Option<Pubkey>,
+    /// delegated lamports of the stake account
+    pub actual_lamports: Option<u64>,
+}
+
+impl JoinedStake {
+    /// `actual - last_update_delegated_lamports`
+    pub fn unrecognized_lamports(&self) -> Option<i128> {
+        self.actual_lamports
+            .map(|actual| actual as i128 - self.record.last_update_delegated_lamports as i128)
+    }
+}
+
+/// Stakes of one validator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValidatorStakes {
+    pub validator_account: Pubkey,
+    pub stake_count: u32,
+    /// sum of `last_update_delegated_lamports`
+    pub recorded_lamports: u64,
+    /// sum of delegated lamports of the stake accounts
+    pub actual_lamports: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StakeView {
+    /// In list order
+    pub stakes: Vec<JoinedStake>,
+    /// In order of the first stake of the validator
+    pub validators: Vec<ValidatorStakes>,
+}
+
+impl StakeView {
+    pub fn with_status(&self, status: StakeStatus) -> impl Iterator<Item = &JoinedStake> {
+        self.stakes
+            .iter()
+            .filter(move |stake| stake.status == status)
+    }
+
+    /// There are stakes waiting for `update_active` or `update_deactivated`
+    pub fn needs_update(&self) -> bool {
+        self.stakes
+            .iter()
+            .any(|stake| matches!(stake.status, StakeStatus::OutOfDate | StakeStatus::Inactive))
+    }
+}
+
+/// Joins the records of the stake list with `stake_accounts` by stake account address
+pub fn join_stakes(
+    stake_system: &StakeSystem,
+    stake_list_data: &[u8],
+    stake_accounts: &[(Pubkey, StakeState)],
+    clock: &Clock,
+) -> Result<StakeView, ProgramError> {
+    let stake_states: HashMap<&Pubkey, &StakeState> = stake_accounts
+        .iter()
+        .map(|(address, stake_state)| (address, stake_state))
+        .collect();
+    let mut stakes = Vec::new();
+    let mut validators: Vec<ValidatorStakes> = Vec::new();
+    for index in 0..stake_system.stake_count() {
+        let record = stake_system.get(stake_list_data, index)?;
+        let delegation = stake_states
+            .get(&record.stake_account)
+            .and_then(|stake_state| stake_state.delegation());
+        let delegation = match delegation {
+            Some(delegation) => delegation,
+            None => {
+                stakes.push(JoinedStake {
+                    index,
+                    record,
+                    status: StakeStatus::Missing,
+                    validator_account: None,
+                    actual_lamports: None,
+                });
+                continue;
+            }
+        };
+        // a fully deactivated emergency stake still waits for update_deactivated
+        let status = if delegation.deactivation_epoch < clock.epoch {
+            StakeStatus::Inactive
+        } else if record.is_emergency_unstaking != 0 {
+            StakeStatus::EmergencyUnstaking
+        } else if delegation.deactivation_epoch != u64::MAX {
+            StakeStatus::Deactivating
+        } else if delegation.activation_epoch >= clock.epoch {
+            StakeStatus::Activating
+        } else if record.last_update_epoch < clock.epoch {
+            StakeStatus::OutOfDate
+        } else {
+            StakeStatus::Active
+        };
+
+        match validators
+            .iter_mut()
+            .find(|validator| validator.validator_account == delegation.voter_pubkey)
+        {
+            Some(validator) => {
+                validator.stake_count += 1;
+                validator.recorded_lamports += record.last_update_delegated_lamports;
+                validator.actual_lamports += delegation.stake;
+            }
+            None => validators.push(ValidatorStakes {
+                validator_account: delegation.voter_pubkey,
+                stake_count: 1,
+                recorded_lamports: record.last_update_delegated_lamports,
+                actual_lamports: delegation.stake,
+            }),
+        }
+        stakes.push(JoinedStake {
+            index,
+            record,
+            status,
+            validator_account: Some(delegation.voter_pubkey),
+            actual_lamports: Some(delegation.stake),
+        });
+    }
+    Ok(StakeView { stakes, validators })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::list::List;
+    use borsh::BorshSerialize;
+    use solana_program::stake::state::{Meta, Stake};
+
+    fn stake(
+        voter: Pubkey,
+        lamports: u64,
+        activation_epoch: u64,
+        deactivation_epoch: u64,
+    ) -> StakeState {
+        let mut stake = Stake::default();
+        stake.delegation.voter_pubkey = voter;
+        stake.delegation.stake = lamports;
+        stake.delegation.activation_epoch = activation_epoch;
+        stake.delegation.deactivation_epoch = deactivation_epoch;
+        StakeState::Stake(Meta::default(), stake)
+    }
+
+    #[test]
+    fn classifies_stakes() {
+        let validator = Pubkey::new_unique();
+        let accounts: Vec<Pubkey> = (0..8).map(|_| Pubkey::new_unique()).collect();
+        let mut data = StakeRecord::DISCRIMINATOR.to_vec();
+        for (index, stake_account) in accounts.iter().enumerate() {
+            StakeRecord {
+                stake_account: *stake_account,
+                last_update_delegated_lamports: 100,
+                last_update_epoch: if index == 1 { 9 } else { 10 },
+                is_emergency_unstaking: (index == 5 || index == 7) as u8,
+            }
+            .serialize(&mut data)
+            .unwrap();
+        }
+        let stake_system = StakeSystem {
+            stake_list: List {
+                item_size: StakeRecord::default().try_to_vec().unwrap().len() as u32,
+                count: accounts.len() as u32,
+                ..List::default()
+            },
+            ..StakeSystem::default()
+        };
+        let stake_accounts = vec![
+            (accounts[0], stake(validator, 100, 1, u64::MAX)),
+            (accounts[1], stake(validator, 110, 1, u64::MAX)),
+            (accounts[2], stake(validator, 100, 10, u64::MAX)),
+            (accounts[3], stake(validator, 100, 1, 10)),
+            (accounts[4], stake(validator, 100, 1, 9)),
+            (accounts[5], stake(validator, 100, 1, 10)),
+            (accounts[7], stake(validator, 100, 1, 9)),
+        ];
+        let clock = Clock {
+            epoch: 10,
+            ..Clock::default()
+        };
+        let view = join_stakes(&stake_system, &data, &stake_accounts, &clock).unwrap();
+        let statuses: Vec<StakeStatus> = view.stakes.iter().map(|stake| stake.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                StakeStatus::Active,
+                StakeStatus::OutOfDate,
+                StakeStatus::Activating,
+                StakeStatus::Deactivating,
+                StakeStatus::Inactive,
+                StakeStatus::EmergencyUnstaking,
+                StakeStatus::Missing,
+                StakeStatus::Inactive,
+            ]
+        );
+        assert!(view.needs_update());
+        assert_eq!(
+            view.with_status(StakeStatus::Inactive)
+                .map(|stake| stake.index)
+                .collect::<Vec<u32>>(),
+            vec![4, 7]
+        );
+        assert_eq!(view.stakes[1].unrecognized_lamports(), Some(10));
+        assert_eq!(
+            view.validators,
+            vec![ValidatorStakes {
+                validator_account: validator,
+                stake_count: 7,
+                recorded_lamports: 700,
+                actual_lamports: 710,
+            }]
+        );
+    }
+
+    #[test]
+    fn deactivated_emergency_stake_needs_update() {
+        let stake_account = Pubkey::new_unique();
+        let record = StakeRecord {
+            stake_account,
+            last_update_delegated_lamports: 100,
+            last_update_epoch: 10,
+            is_emergency_unstaking: 1,
+        };
+        let mut data = StakeRecord::DISCRIMINATOR.to_vec();
+        record.serialize(&mut data).unwrap();
+        let stake_system = StakeSystem {
+            stake_list: List {
+                item_size: StakeRecord::default().try_to_vec().unwrap().len() as u32,
+                count: 1,
+                ..List::default()
+            },
+            ..StakeSystem::default()
+        };
+        let stake_accounts = vec![(stake_account, stake(Pubkey::new_unique(), 100, 1, 10))];
+        let clock = |epoch| Clock {
+            epoch,
+            ..Clock::default()
+        };
+
+        let view = join_stakes(&stake_system, &data, &stake_accounts, &clock(10)).unwrap();
+        assert_eq!(view.stakes[0].status, StakeStatus::EmergencyUnstaking);
+        assert!(!view.needs_update());
+        let view = join_stakes(&stake_system, &data, &stake_accounts, &clock(11)).unwrap();
+        assert_eq!(view.stakes[0].status, StakeStatus::Inactive);
+        assert!(view.needs_update());
+    }
+}
@@ -1,17 +1,127 @@
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use micro_anchor::{Discriminator, Owner, AccountDeserialize};
-use solana_program::pubkey::Pubkey;
+use solana_program::{
+    clock::{Clock, DEFAULT_MS_PER_SLOT},
+    epoch_schedule::EpochSchedule,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
 
+use crate::{error::CommonError, state::marinade::Marinade};
 
-
-#[derive(Debug, BorshDeserialize, BorshSerialize)]
+#[derive(Clone, Debug, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DelayedUnstakeTicket {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub state_address: Pubkey, // instance of marinade state this ticket belongs to
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub beneficiary: Pubkey,   // main account where to send SOL when claimed
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub lamports_amount: u64,  // amount this ticked is worth
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub created_epoch: u64, // epoch when this acc was created (epoch when delayed-unstake was requested)
 }
 
+impl DelayedUnstakeTicket {
+    /// Claim is rejected during this time after the start of the first due epoch
+    /// (stake accounts must be deactivated and withdrawn into the reserve first)
+    pub const WAIT_TIME_FOR_CLAIM_SECONDS: i64 = 30 * 60;
+
+    /// First epoch the ticket can be claimed in
+    pub fn due_epoch(&self) -> u64 {
+        self.created_epoch + 1
+    }
+
+    /// What claim would do now and when it is expected to succeed.
+    /// `reserve_balance` is the reserve PDA lamports.
+    /// Time is estimated with `DEFAULT_MS_PER_SLOT` so it may be a bit early
+    pub fn readiness(
+        &self,
+        state: &Marinade,
+        reserve_balance: u64,
+        clock: &Clock,
+        epoch_schedule: &EpochSchedule,
+    ) -> TicketReadiness {
+        let due_epoch = self.due_epoch();
+        let due_slot = epoch_schedule.get_first_slot_in_epoch(due_epoch);
+        let estimated_claimable_timestamp = if clock.epoch > due_epoch {
+            // the wait time passed in the due epoch
+            clock.unix_timestamp
+        } else {
+            let due_epoch_start_timestamp = if clock.epoch == due_epoch {
+                clock.epoch_start_timestamp
+            } else {
+                let ms = due_slot.saturating_sub(clock.slot) * DEFAULT_MS_PER_SLOT;
+                clock.unix_timestamp + (ms / 1000) as i64
+            };
+            (due_epoch_start_timestamp + Self::WAIT_TIME_FOR_CLAIM_SECONDS)
+                .max(clock.unix_timestamp)
+        };
+        let reserve_can_pay =
+            reserve_balance.saturating_sub(state.rent_exempt_for_token_acc) >= self.lamports_amount;
+        let status = if clock.epoch < due_epoch {
+            TicketStatus::NotDue
+        } else if clock.unix_timestamp < estimated_claimable_timestamp {
+            TicketStatus::NotReady
+        } else if !reserve_can_pay {
+            TicketStatus::InsufficientReserve
+        } else {
+            TicketStatus::Claimable
+        };
+        let last_stake_delta_epoch = state.stake_system.last_stake_delta_epoch;
+        TicketReadiness {
+            status,
+            due_epoch,
+            due_slot,
+            estimated_claimable_timestamp,
+            reserve_can_pay,
+            unstake_started: last_stake_delta_epoch != u64::MAX
+                && last_stake_delta_epoch >= self.created_epoch,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TicketStatus {
+    /// The epoch of the ticket did not end yet
+    NotDue,
+    /// Inside the wait time at the start of the due epoch
+    NotReady,
+    /// Due, but the reserve does not have the lamports yet (stakes are not withdrawn)
+    InsufficientReserve,
+    Claimable,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TicketReadiness {
+    pub status: TicketStatus,
+    pub due_epoch: u64,
+    /// first slot of `due_epoch`
+    pub due_slot: u64,
+    /// Approximate unix timestamp when claim stops failing because of epoch and wait time.
+    /// Current time if it already passed
+    pub estimated_claimable_timestamp: i64,
+    pub reserve_can_pay: bool,
+    /// stake delta already ran in or after the epoch of the ticket so its lamports are being unstaked
+    pub unstake_started: bool,
+}
+
+impl TicketReadiness {
+    pub fn is_claimable(&self) -> bool {
+        self.status == TicketStatus::Claimable
+    }
+
+    /// The error claim would fail with now
+    pub fn error(&self) -> Option<ProgramError> {
+        match self.status {
+            TicketStatus::NotDue => Some(CommonError::TicketNotDue.into()),
+            TicketStatus::NotReady => Some(CommonError::TicketNotReady.into()),
+            TicketStatus::InsufficientReserve => Some(ProgramError::InsufficientFunds),
+            TicketStatus::Claimable => None,
+        }
+    }
+}
+
 impl Discriminator for DelayedUnstakeTicket {
     const DISCRIMINATOR: [u8; 8] = [133, 77, 18, 98, 211, 1, 231, 3];
 }
@@ -22,4 +132,112 @@ impl Owner for DelayedUnstakeTicket {
     }
 }
 
-impl AccountDeserialize for DelayedUnstakeTicket {}
\ No newline at end of file
+impl AccountDeserialize for DelayedUnstakeTicket {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticket_and_state() -> (DelayedUnstakeTicket, Marinade) {
+        let ticket = DelayedUnstakeTicket {
+            state_address: Pubkey::default(),
+            beneficiary: Pubkey::default(),
+            lamports_amount: 1_000,
+            created_epoch: 10,
+        };
+        let mut state = Marinade {
+            rent_exempt_for_token_acc: 10,
+            ..Marinade::default()
+        };
+        state.stake_system.last_stake_delta_epoch = 10;
+        (ticket, state)
+    }
+
+    fn clock(epoch: u64, slot_in_epoch: u64, epoch_start_timestamp: i64, elapsed: i64) -> Clock {
+        let slots_per_epoch = EpochSchedule::without_warmup().slots_per_epoch;
+        Clock {
+            slot: epoch * slots_per_epoch + slot_in_epoch,
+            epoch_start_timestamp,
+            epoch,
+            leader_schedule_epoch: epoch + 1,
+            unix_timestamp: epoch_start_timestamp + elapsed,
+        }
+    }
+
+    #[test]
+    fn readiness() {
+        let epoch_schedule = EpochSchedule::without_warmup();
+        let (ticket, state) = ticket_and_state();
+        let slots_per_epoch = epoch_schedule.slots_per_epoch;
+
+        let clock_before = clock(10, 100, 1_000_000, 40);
+        let readiness = ticket.readiness(&state, 1_010, &clock_before, &epoch_schedule);
+        assert_eq!(readiness.status, TicketStatus::NotDue);
+        assert_eq!(readiness.due_slot, 11 * slots_per_epoch);
+        assert!(readiness.unstake_started);
+        assert_eq!(
+            readiness.estimated_claimable_timestamp,
+            1_000_040
+                + ((slots_per_epoch - 100) * DEFAULT_MS_PER_SLOT / 1000) as i64
+                + DelayedUnstakeTicket::WAIT_TIME_FOR_CLAIM_SECONDS
+        );
+
+        let clock_due = clock(11, 900, 2_000_000, 1_800);
+        let readiness = ticket.readiness(&state, 1_009, &clock_due, &epoch_schedule);
+        assert_eq!(readiness.status, TicketStatus::InsufficientReserve);
+        let readiness = ticket.readiness(&state, 1_010, &clock_due, &epoch_schedule);
+        assert!(readiness.is_claimable());
+        assert_eq!(readiness.error(), None);
+        assert_eq!(readiness.estimated_claimable_timestamp, 2_001_800);
+    }
+
+    #[test]
+    fn readiness_in_wait_window() {
+        let epoch_schedule = EpochSchedule::without_warmup();
+        let (ticket, state) = ticket_and_state();
+
+        let early = clock(11, 5, 2_000_000, 2);
+        let readiness = ticket.readiness(&state, 1_010, &early, &epoch_schedule);
+        assert_eq!(readiness.status, TicketStatus::NotReady);
+        assert_eq!(readiness.error(), Some(CommonError::TicketNotReady.into()));
+        assert_eq!(readiness.estimated_claimable_timestamp, 2_001_800);
+
+        let last_second = clock(11, 800, 2_000_000, 1_799);
+        let readiness = ticket.readiness(&state, 1_010, &last_second, &epoch_schedule);
+        assert_eq!(readiness.status, TicketStatus::NotReady);
+        assert_eq!(readiness.estimated_claimable_timestamp, 2_001_800);
+    }
+
+    #[test]
+    fn readiness_in_later_epoch() {
+        let epoch_schedule = EpochSchedule::without_warmup();
+        let (ticket, state) = ticket_and_state();
+
+        // right after the start of a later epoch the wait time does not apply
+        let later = clock(12, 5, 3_000_000, 2);
+        let readiness = ticket.readiness(&state, 1_010, &later, &epoch_schedule);
+        assert!(readiness.is_claimable());
+        assert_eq!(readiness.estimated_claimable_timestamp, 3_000_002);
+
+        let readiness = ticket.readiness(&state, 1_009, &later, &epoch_schedule);
+        assert_eq!(readiness.status, TicketStatus::InsufficientReserve);
+        assert_eq!(readiness.estimated_claimable_timestamp, 3_000_002);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let ticket = DelayedUnstakeTicket {
+            state_address: Pubkey::new_unique(),
+            beneficiary: Pubkey::new_unique(),
+            lamports_amount: u64::MAX,
+            created_epoch: 10,
+        };
+        let json = serde_json::to_value(&ticket).unwrap();
+        assert_eq!(json["beneficiary"], ticket.beneficiary.to_string());
+        assert_eq!(json["lamports_amount"], u64::MAX.to_string());
+        assert_eq!(json["created_epoch"], "10");
+        let parsed: DelayedUnstakeTicket = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.try_to_vec().unwrap(), ticket.try_to_vec().unwrap());
+    }
+}
@@ -1,11 +1,21 @@
 use std::{fmt::Display, str::FromStr};
 
-use borsh::{BorshSerialize, BorshDeserialize};
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 
 use crate::error::CommonError;
 
 #[derive(
-    Clone, Copy, Debug, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq, PartialOrd, Ord,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    BorshSerialize,
+    BorshDeserialize,
+    BorshSchema,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
 )]
 pub struct Fee {
     pub basis_points: u32,
@@ -18,6 +28,8 @@ impl Display for Fee {
 }
 
 impl Fee {
+    pub const SERIALIZED_LEN: usize = 4;
+
     pub fn from_basis_points(basis_points: u32) -> Self {
         Self { basis_points }
     }
@@ -60,4 +72,49 @@ impl FromStr for Fee {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         f64::try_into(s.parse().map_err(|_| CommonError::CalculationFailure)?)
     }
-}
\ No newline at end of file
+}
+
+/// `{"basis_points": 250, "percent": "2.5%"}`. Only `basis_points` is read back
+#[cfg(feature = "serde")]
+impl serde::Serialize for Fee {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut fee = serializer.serialize_struct("Fee", 2)?;
+        fee.serialize_field("basis_points", &self.basis_points)?;
+        fee.serialize_field("percent", &self.to_string())?;
+        fee.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Fee {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct FeeJson {
+            basis_points: u32,
+        }
+        let FeeJson { basis_points } = FeeJson::deserialize(deserializer)?;
+        Ok(Self::from_basis_points(basis_points))
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_percent_is_ignored_on_input() {
+        let fee = Fee::from_basis_points(250);
+        let json = serde_json::to_value(fee).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"basis_points": 250, "percent": "2.5%"})
+        );
+        assert_eq!(serde_json::from_value::<Fee>(json).unwrap(), fee);
+        let fee: Fee =
+            serde_json::from_str(r#"{"basis_points": 100, "percent": "50%"}"#).unwrap();
+        assert_eq!(fee.basis_points, 100);
+        let fee: Fee = serde_json::from_str(r#"{"basis_points": 100}"#).unwrap();
+        assert_eq!(fee.basis_points, 100);
+    }
+}
@@ -0,0 +1,212 @@
+//! Known layouts of the marinade state account
+//!
+//! The known layouts differ only by fields at the end of `Marinade`, so an older layout
+//! is a prefix of the current one and the missing fields are read as zero.
+//! State accounts may be allocated bigger than the layout with zeroed trailing bytes.
+
+use std::fmt::Display;
+
+use borsh::BorshDeserialize;
+use micro_anchor::Discriminator;
+
+use crate::state::marinade::Marinade;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MarinadeLayout {
+    /// `Marinade` without the trailing `emergency_cooling_down` field
+    V1,
+    /// Current `Marinade` struct
+    V2,
+}
+
+impl MarinadeLayout {
+    /// Oldest first
+    pub const ALL: &'static [MarinadeLayout] = &[MarinadeLayout::V1, MarinadeLayout::V2];
+    pub const LATEST: MarinadeLayout = MarinadeLayout::V2;
+
+    /// Account data length including the discriminator
+    pub const fn data_len(self) -> usize {
+        match self {
+            // emergency_cooling_down: u64
+            MarinadeLayout::V1 => Marinade::serialized_len() - 8,
+            MarinadeLayout::V2 => Marinade::serialized_len(),
+        }
+    }
+
+    /// Recognizes the layout by the account length or by zeroed bytes after the layout
+    pub fn detect(data: &[u8]) -> Result<Self, LayoutError> {
+        if data.len() < 8 || data[..8] != Marinade::DISCRIMINATOR {
+            return Err(LayoutError::DiscriminatorMismatch);
+        }
+        if let Some(layout) = Self::ALL
+            .iter()
+            .find(|layout| layout.data_len() == data.len())
+        {
+            return Ok(*layout);
+        }
+        let oldest_len = Self::ALL[0].data_len();
+        if data.len() < oldest_len {
+            return Err(LayoutError::TooShort {
+                data_len: data.len(),
+                min_len: oldest_len,
+            });
+        }
+        // newest first: an older layout padded with zeros parses the same as a newer one
+        Self::ALL
+            .iter()
+            .rev()
+            .filter(|layout| layout.data_len() <= data.len())
+            .find(|layout| data[layout.data_len()..].iter().all(|b| *b == 0))
+            .copied()
+            .ok_or_else(|| {
+                // the newest layout fitting into the data. At least the oldest one fits
+                let known_len = Self::ALL
+                    .iter()
+                    .rev()
+                    .map(|layout| layout.data_len())
+                    .find(|len| *len <= data.len())
+                    .unwrap_or(oldest_len);
+                LayoutError::UnknownLayout {
+                    data_len: data.len(),
+                    known_len,
+                    non_zero_offset: known_len
+                        + data[known_len..]
+                            .iter()
+                            .position(|b| *b != 0)
+                            .unwrap_or(data.len() - known_len),
+                }
+            })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    DiscriminatorMismatch,
+    TooShort {
+        data_len: usize,
+        min_len: usize,
+    },
+    /// Non zero data after the longest known layout fitting into the data.
+    /// Probably written by a newer program
+    UnknownLayout {
+        data_len: usize,
+        /// length of that layout
+        known_len: usize,
+        non_zero_offset: usize,
+    },
+    Deserialize(String),
+}
+
+impl Display for LayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutError::DiscriminatorMismatch => write!(f, "Not a marinade state account"),
+            LayoutError::TooShort { data_len, min_len } => write!(
+                f,
+                "State account data too short {} (min is {})",
+                data_len, min_len
+            ),
+            LayoutError::UnknownLayout {
+                data_len,
+                known_len,
+                non_zero_offset,
+            } => write!(
+                f,
+                "Unknown state layout of {} bytes: non zero byte at {} after a known layout ({} bytes)",
+                data_len, non_zero_offset, known_len
+            ),
+            LayoutError::Deserialize(e) => write!(f, "Can not deserialize state: {}", e),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VersionedMarinade {
+    pub layout: MarinadeLayout,
+    pub state: Marinade,
+}
+
+impl VersionedMarinade {
+    pub fn deserialize(data: &[u8]) -> Result<Self, LayoutError> {
+        let layout = MarinadeLayout::detect(data)?;
+        let mut buf = data[8..layout.data_len()].to_vec();
+        // fields added after the layout are zero
+        buf.resize(Marinade::SERIALIZED_LEN, 0);
+        let state = Marinade::deserialize(&mut buf.as_slice())
+            .map_err(|e| LayoutError::Deserialize(e.to_string()))?;
+        Ok(Self { layout, state })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshSerialize;
+
+    fn account_data(state: &Marinade, len: usize) -> Vec<u8> {
+        let mut data = Marinade::DISCRIMINATOR.to_vec();
+        state.serialize(&mut data).unwrap();
+        data.resize(len, 0);
+        data
+    }
+
+    #[test]
+    fn serialized_len_matches_borsh() {
+        assert_eq!(
+            Marinade::default().try_to_vec().unwrap().len(),
+            Marinade::SERIALIZED_LEN
+        );
+        assert_eq!(
+            crate::state::liq_pool::LiqPool::default()
+                .try_to_vec()
+                .unwrap()
+                .len(),
+            crate::state::liq_pool::LiqPool::SERIALIZED_LEN
+        );
+    }
+
+    #[test]
+    fn detects_layouts() {
+        let state = Marinade {
+            min_deposit: 1,
+            emergency_cooling_down: 5,
+            ..Marinade::default()
+        };
+        let v1 = account_data(&state, MarinadeLayout::V1.data_len());
+        let parsed = VersionedMarinade::deserialize(&v1).unwrap();
+        assert_eq!(parsed.layout, MarinadeLayout::V1);
+        assert_eq!(parsed.state.min_deposit, 1);
+        assert_eq!(parsed.state.emergency_cooling_down, 0);
+
+        let padded = account_data(&state, Marinade::serialized_len() + 100);
+        let parsed = VersionedMarinade::deserialize(&padded).unwrap();
+        assert_eq!(parsed.layout, MarinadeLayout::V2);
+        assert_eq!(parsed.state.emergency_cooling_down, 5);
+
+        let mut newer = padded;
+        newer[Marinade::serialized_len() + 3] = 1;
+        assert_eq!(
+            MarinadeLayout::detect(&newer),
+            Err(LayoutError::UnknownLayout {
+                data_len: Marinade::serialized_len() + 100,
+                known_len: Marinade::serialized_len(),
+                non_zero_offset: Marinade::serialized_len() + 3,
+            })
+        );
+        // shorter than V2 with non zero bytes after V1
+        let mut between = account_data(&Marinade::default(), MarinadeLayout::V1.data_len() + 4);
+        between[MarinadeLayout::V1.data_len() + 2] = 1;
+        assert_eq!(
+            MarinadeLayout::detect(&between),
+            Err(LayoutError::UnknownLayout {
+                data_len: MarinadeLayout::V1.data_len() + 4,
+                known_len: MarinadeLayout::V1.data_len(),
+                non_zero_offset: MarinadeLayout::V1.data_len() + 2,
+            })
+        );
+        assert!(matches!(
+            MarinadeLayout::detect(&v1[..100]),
+            Err(LayoutError::TooShort { .. })
+        ));
+    }
+}
@@ -1,22 +1,37 @@
-use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::{entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use solana_program::{
+    entrypoint::ProgramResult,
+    msg,
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    pubkey::{Pubkey, PUBKEY_BYTES},
+};
 
 use crate::{
-    calc::proportional, checks::check_address, error::CommonError, state::fee::Fee, located::Located,
-    state::marinade::Marinade, ID,
+    calc::{proportional, value_from_shares, MsolPrice, Rounding},
+    checks::check_address,
+    error::{CommonError, CAP_REACHED_ERROR_CODE},
+    located::Located,
+    state::fee::Fee,
+    state::marinade::Marinade,
+    ID,
 };
 
-#[derive(Clone, BorshDeserialize, BorshSerialize, Debug)]
+#[derive(Clone, Default, BorshDeserialize, BorshSerialize, BorshSchema, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LiqPool {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub lp_mint: Pubkey,
     pub lp_mint_authority_bump_seed: u8,
     pub sol_leg_bump_seed: u8,
     pub msol_leg_authority_bump_seed: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub msol_leg: Pubkey,
 
     //The next 3 values define the SOL/mSOL Liquidity pool fee curve params
     // We assume this pool is always UNBALANCED, there should be more SOL than mSOL 99% of the time
     ///Liquidity target. If the Liquidity reach this amount, the fee reaches lp_min_discount_fee
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub lp_liquidity_target: u64, // 10_000 SOL initially
     /// Liquidity pool max fee
     pub lp_max_fee: Fee, //3% initially
@@ -25,8 +40,11 @@ pub struct LiqPool {
     /// Treasury cut
     pub treasury_cut: Fee, //2500 => 25% how much of the Liquid unstake fee goes to treasury_msol_account
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub lp_supply: u64, // virtual lp token supply. May be > real supply because of burning tokens. Use UpdateLiqPool to align it with real value
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub lent_from_sol_leg: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub liquidity_sol_cap: u64,
 }
 
@@ -36,6 +54,19 @@ impl LiqPool {
     pub const MSOL_LEG_AUTHORITY_SEED: &'static [u8] = b"liq_st_sol_authority";
     pub const MSOL_LEG_SEED: &'static str = "liq_st_sol";
 
+    pub const SERIALIZED_LEN: usize = PUBKEY_BYTES // lp_mint
+        + 1 // lp_mint_authority_bump_seed
+        + 1 // sol_leg_bump_seed
+        + 1 // msol_leg_authority_bump_seed
+        + PUBKEY_BYTES // msol_leg
+        + 8 // lp_liquidity_target
+        + Fee::SERIALIZED_LEN // lp_max_fee
+        + Fee::SERIALIZED_LEN // lp_min_fee
+        + Fee::SERIALIZED_LEN // treasury_cut
+        + 8 // lp_supply
+        + 8 // lent_from_sol_leg
+        + 8; // liquidity_sol_cap
+
     pub fn find_lp_mint_authority(state: &Pubkey) -> (Pubkey, u8) {
         Pubkey::find_program_address(
             &[&state.to_bytes()[..32], Self::LP_MINT_AUTHORITY_SEED],
@@ -59,11 +90,11 @@ impl LiqPool {
     }
 
     pub fn check_lp_mint(&mut self, lp_mint: &Pubkey) -> ProgramResult {
-        check_address(lp_mint, &self.lp_mint, "lp_mint")
+        check_address(lp_mint, &self.lp_mint, "lp_mint").map_err(Into::into)
     }
 
     pub fn check_liq_pool_msol_leg(&self, liq_pool_msol_leg: &Pubkey) -> ProgramResult {
-        check_address(liq_pool_msol_leg, &self.msol_leg, "liq_pool_msol_leg")
+        check_address(liq_pool_msol_leg, &self.msol_leg, "liq_pool_msol_leg").map_err(Into::into)
     }
 
     pub fn delta(&self) -> u32 {
@@ -117,10 +148,124 @@ impl LiqPool {
                 result_amount,
                 self.liquidity_sol_cap
             );
-            return Err(ProgramError::Custom(3782));
+            return Err(ProgramError::Custom(CAP_REACHED_ERROR_CODE));
         }
         Ok(())
     }
+
+    /// Pool value in lamports. mSOL leg is valued the same way as by add_liquidity
+    pub fn value(&self, legs: &LiqPoolLegs) -> Result<u64, CommonError> {
+        legs.sol_leg_balance
+            .checked_add(
+                legs.msol_price
+                    .to_lamports(legs.msol_leg_balance, Rounding::Floor)?,
+            )
+            .ok_or(CommonError::CalculationFailure)
+    }
+
+    /// Lamports value of one LP token (LAMPORTS_PER_SOL of LP mint units).
+    /// LP tokens of an empty pool are minted 1:1
+    pub fn lp_token_price(&self, legs: &LiqPoolLegs) -> Result<u64, CommonError> {
+        value_from_shares(LAMPORTS_PER_SOL, self.value(legs)?, self.lp_supply)
+    }
+
+    /// What `lp_tokens` would get by remove_liquidity
+    pub fn lp_position(
+        &self,
+        lp_tokens: u64,
+        legs: &LiqPoolLegs,
+    ) -> Result<LpPosition, CommonError> {
+        if self.lp_supply == 0 {
+            // nothing to withdraw from an empty pool
+            return Ok(LpPosition {
+                lp_tokens,
+                sol_lamports: 0,
+                msol_amount: 0,
+                msol_value: 0,
+                value: 0,
+                pool_share: 0.0,
+            });
+        }
+        let sol_lamports = proportional(lp_tokens, legs.sol_leg_balance, self.lp_supply)?;
+        let msol_amount = proportional(lp_tokens, legs.msol_leg_balance, self.lp_supply)?;
+        let msol_value = legs.msol_price.to_lamports(msol_amount, Rounding::Floor)?;
+        Ok(LpPosition {
+            lp_tokens,
+            sol_lamports,
+            msol_amount,
+            msol_value,
+            value: sol_lamports
+                .checked_add(msol_value)
+                .ok_or(CommonError::CalculationFailure)?,
+            pool_share: lp_tokens as f64 / self.lp_supply as f64,
+        })
+    }
+
+    /// Splits the value change of `lp_tokens` held from this snapshot to the `later` one
+    /// into fees earned and mSOL price change.
+    /// It is an estimation: the earlier legs are revalued with the later mSOL price
+    pub fn accrued_fees(
+        &self,
+        legs: &LiqPoolLegs,
+        later: &LiqPool,
+        later_legs: &LiqPoolLegs,
+        lp_tokens: u64,
+    ) -> Result<LpFeeAccrual, CommonError> {
+        let value_before = self.lp_position(lp_tokens, legs)?.value;
+        let value_before_at_later_price = self
+            .lp_position(
+                lp_tokens,
+                &LiqPoolLegs {
+                    msol_price: later_legs.msol_price,
+                    ..*legs
+                },
+            )?
+            .value;
+        let value_after = later.lp_position(lp_tokens, later_legs)?.value;
+        Ok(LpFeeAccrual {
+            fees: value_after as i128 - value_before_at_later_price as i128,
+            msol_price_change: value_before_at_later_price as i128 - value_before as i128,
+        })
+    }
+}
+
+/// Liq pool leg balances at some moment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiqPoolLegs {
+    /// SOL leg lamports minus rent_exempt_for_token_acc
+    pub sol_leg_balance: u64,
+    pub msol_leg_balance: u64,
+    pub msol_price: MsolPrice,
+}
+
+impl LiqPoolLegs {
+    pub fn new(state: &Marinade, sol_leg_balance: u64, msol_leg_balance: u64) -> Self {
+        Self {
+            sol_leg_balance,
+            msol_leg_balance,
+            msol_price: state.exact_msol_price(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LpPosition {
+    pub lp_tokens: u64,
+    pub sol_lamports: u64,
+    pub msol_amount: u64,
+    /// msol_amount in lamports
+    pub msol_value: u64,
+    pub value: u64,
+    /// lp_tokens / lp_supply
+    pub pool_share: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LpFeeAccrual {
+    /// lamports earned from liquid unstake fees
+    pub fees: i128,
+    /// lamports gained (or lost) by revaluing the mSOL leg
+    pub msol_price_change: i128,
 }
 
 pub trait LiqPoolHelpers {
@@ -194,6 +339,7 @@ where
             &self.lp_mint_authority(),
             "lp_mint_authority",
         )
+        .map_err(Into::into)
     }
 
     fn check_liq_pool_sol_leg_pda(&self, liq_pool_sol_leg_pda: &Pubkey) -> ProgramResult {
@@ -202,6 +348,7 @@ where
             &self.liq_pool_sol_leg_address(),
             "liq_pool_sol_leg_pda",
         )
+        .map_err(Into::into)
     }
 
     fn check_liq_pool_msol_leg_authority(
@@ -213,5 +360,83 @@ where
             &self.liq_pool_msol_leg_authority(),
             "liq_pool_msol_leg_authority",
         )
+        .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(lp_supply: u64) -> LiqPool {
+        LiqPool {
+            lp_supply,
+            ..LiqPool::default()
+        }
+    }
+
+    fn legs(sol_leg_balance: u64, msol_leg_balance: u64, price: (u128, u128)) -> LiqPoolLegs {
+        LiqPoolLegs {
+            sol_leg_balance,
+            msol_leg_balance,
+            msol_price: MsolPrice {
+                numerator: price.0,
+                denominator: price.1,
+            },
+        }
+    }
+
+    #[test]
+    fn value_and_lp_token_price() {
+        let legs = legs(1_000, 500, (3, 2));
+        assert_eq!(pool(1_750).value(&legs).unwrap(), 1_750);
+        assert_eq!(pool(1_750).lp_token_price(&legs).unwrap(), LAMPORTS_PER_SOL);
+        assert_eq!(
+            pool(3_500).lp_token_price(&legs).unwrap(),
+            LAMPORTS_PER_SOL / 2
+        );
+        assert_eq!(pool(0).lp_token_price(&legs).unwrap(), LAMPORTS_PER_SOL);
+    }
+
+    #[test]
+    fn lp_position() {
+        let legs = legs(1_000, 500, (3, 2));
+        let position = pool(1_000).lp_position(250, &legs).unwrap();
+        assert_eq!(position.sol_lamports, 250);
+        assert_eq!(position.msol_amount, 125);
+        // 187.5 rounded down
+        assert_eq!(position.msol_value, 187);
+        assert_eq!(position.value, 437);
+        assert_eq!(position.pool_share, 0.25);
+
+        let empty = pool(0).lp_position(250, &legs).unwrap();
+        assert_eq!(empty.value, 0);
+        assert_eq!(empty.pool_share, 0.0);
+    }
+
+    #[test]
+    fn accrued_fees() {
+        let earlier = legs(1_000, 500, (3, 2));
+        // 100 lamports of fees and the mSOL price grown to 2
+        let later = legs(1_100, 500, (2, 1));
+        let accrual = pool(1_000)
+            .accrued_fees(&earlier, &pool(1_000), &later, 1_000)
+            .unwrap();
+        assert_eq!(
+            accrual,
+            LpFeeAccrual {
+                fees: 100,
+                msol_price_change: 250,
+            }
+        );
+        assert_eq!(
+            pool(0)
+                .accrued_fees(&earlier, &pool(1_000), &later, 0)
+                .unwrap(),
+            LpFeeAccrual {
+                fees: 0,
+                msol_price_change: 0,
+            }
+        );
     }
 }
@@ -1,20 +1,34 @@
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
-use solana_program::{pubkey::Pubkey, program_error::ProgramError, msg};
+use solana_program::{
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::{Pubkey, PUBKEY_BYTES},
+};
 
 use crate::error::CommonError;
 
 #[derive(Default, Clone, BorshSerialize, BorshDeserialize, BorshSchema, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct List {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub account: Pubkey,
     pub item_size: u32,
     pub count: u32,
     // For chunked change account
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub new_account: Pubkey,
     pub copied_count: u32,
 }
 
 
 impl List {
+    pub const SERIALIZED_LEN: usize = PUBKEY_BYTES // account
+        + 4 // item_size
+        + 4 // count
+        + PUBKEY_BYTES // new_account
+        + 4; // copied_count
+
     pub fn bytes_for(item_size: u32, count: u32) -> u32 {
         8 + count * item_size
     }
@@ -65,8 +79,348 @@ impl List {
             );
             return Err(ProgramError::InvalidArgument);
         }
+        self.check_data(data, list_name)?;
         let start = 8 + (index * self.item_size()) as usize;
         I::deserialize(&mut &data[start..(start + self.item_size() as usize)])
             .map_err(|err| ProgramError::BorshIoError(err.to_string()))
     }
-}
\ No newline at end of file
+
+    /// Empty list in `data` marked with `discriminator`. `data` must not be initialized yet
+    pub fn new(
+        discriminator: &[u8; 8],
+        item_size: u32,
+        account: Pubkey,
+        data: &mut [u8],
+        list_name: &str,
+    ) -> Result<Self, ProgramError> {
+        let result = Self {
+            account,
+            item_size,
+            count: 0,
+            new_account: Pubkey::default(),
+            copied_count: 0,
+        };
+        Self::init_account(discriminator, data, list_name)?;
+        Ok(result)
+    }
+
+    fn init_account(discriminator: &[u8; 8], data: &mut [u8], list_name: &str) -> ProgramResult {
+        if data.len() < 8 {
+            msg!("list {} account is too small", list_name);
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        if data[0..8] != [0; 8] {
+            msg!("list {} account is already initialized", list_name);
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        data[0..8].copy_from_slice(discriminator);
+        Ok(())
+    }
+
+    pub fn set<I: BorshSerialize>(
+        &self,
+        data: &mut [u8],
+        index: u32,
+        item: I,
+        list_name: &str,
+    ) -> ProgramResult {
+        self.check_not_changing(list_name)?;
+        self.check_index(index, list_name)?;
+        self.check_data(data, list_name)?;
+        self.write(data, index, item)
+    }
+
+    pub fn push<I: BorshSerialize>(
+        &mut self,
+        data: &mut [u8],
+        item: I,
+        list_name: &str,
+    ) -> ProgramResult {
+        self.check_not_changing(list_name)?;
+        let capacity = self.capacity(data.len())?;
+        if self.len() >= capacity {
+            msg!("list {} with capacity {} is full", list_name, capacity);
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        self.write(data, self.len(), item)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Moves the last item into the place of the removed one
+    pub fn remove(&mut self, data: &mut [u8], index: u32, list_name: &str) -> ProgramResult {
+        self.check_not_changing(list_name)?;
+        self.check_index(index, list_name)?;
+        self.check_data(data, list_name)?;
+        self.count -= 1;
+        if index == self.count {
+            return Ok(());
+        }
+        let start = 8 + (index * self.item_size()) as usize;
+        let last_item_start = 8 + (self.count * self.item_size()) as usize;
+        data.copy_within(
+            last_item_start..(last_item_start + self.item_size() as usize),
+            start,
+        );
+        Ok(())
+    }
+
+    /// Starts moving the list into `new_account`. Items are copied by `copy_next_chunk`
+    /// and the list can not be modified until it is done
+    pub fn start_changing_account(
+        &mut self,
+        discriminator: &[u8; 8],
+        new_account: Pubkey,
+        new_data: &mut [u8],
+        list_name: &str,
+    ) -> ProgramResult {
+        self.check_not_changing(list_name)?;
+        if new_account == Pubkey::default() || new_account == self.account {
+            msg!("Invalid new account {} for list {}", new_account, list_name);
+            return Err(ProgramError::InvalidArgument);
+        }
+        let new_capacity = self.capacity(new_data.len())?;
+        if new_capacity < self.len() {
+            msg!(
+                "new account of list {} is too small: capacity {} < {} items",
+                list_name,
+                new_capacity,
+                self.len()
+            );
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        Self::init_account(discriminator, new_data, list_name)?;
+        self.new_account = new_account;
+        self.copied_count = 0;
+        Ok(())
+    }
+
+    /// Copies up to `max_items` next items from `data` into `new_data`.
+    /// Returns true when all items are copied and the list is switched to `new_account`
+    pub fn copy_next_chunk(
+        &mut self,
+        data: &[u8],
+        new_data: &mut [u8],
+        max_items: u32,
+        list_name: &str,
+    ) -> Result<bool, ProgramError> {
+        if !self.is_changing_account() {
+            msg!("list {} is not changing account", list_name);
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.check_data(data, list_name)?;
+        let remaining = match self.len().checked_sub(self.copied_count) {
+            Some(remaining) => remaining,
+            None => {
+                msg!(
+                    "list {} copied count {} is over its length {}",
+                    list_name,
+                    self.copied_count,
+                    self.len()
+                );
+                return Err(ProgramError::InvalidAccountData);
+            }
+        };
+        let copy_count = max_items.min(remaining);
+        let start = (self.copied_count as usize)
+            .checked_mul(self.item_size() as usize)
+            .and_then(|offset| offset.checked_add(8))
+            .ok_or(CommonError::CalculationFailure)?;
+        let end = (copy_count as usize)
+            .checked_mul(self.item_size() as usize)
+            .and_then(|size| size.checked_add(start))
+            .ok_or(CommonError::CalculationFailure)?;
+        if new_data.len() < end {
+            msg!("new account of list {} is too small", list_name);
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        new_data[start..end].copy_from_slice(&data[start..end]);
+        self.copied_count += copy_count;
+        if self.copied_count < self.len() {
+            return Ok(false);
+        }
+        self.account = self.new_account;
+        self.new_account = Pubkey::default();
+        self.copied_count = 0;
+        Ok(true)
+    }
+
+    fn check_not_changing(&self, list_name: &str) -> ProgramResult {
+        if self.is_changing_account() {
+            msg!(
+                "Can not modify list {} while changing list's account",
+                list_name
+            );
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(())
+    }
+
+    fn check_index(&self, index: u32, list_name: &str) -> ProgramResult {
+        if index >= self.len() {
+            msg!(
+                "list {} index out of bounds ({}/{})",
+                list_name,
+                index,
+                self.len()
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    /// `data` holds the header and all `count` items
+    fn check_data(&self, data: &[u8], list_name: &str) -> ProgramResult {
+        let required = 8 + self.len() as u64 * self.item_size() as u64;
+        if (data.len() as u64) < required {
+            msg!(
+                "list {} account data is too short: {} < {}",
+                list_name,
+                data.len(),
+                required
+            );
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(())
+    }
+
+    fn write<I: BorshSerialize>(&self, data: &mut [u8], index: u32, item: I) -> ProgramResult {
+        let start = 8 + (index * self.item_size()) as usize;
+        let mut write_area = &mut data[start..(start + self.item_size() as usize)];
+        item.serialize(&mut write_area)
+            .map_err(|err| ProgramError::BorshIoError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISCRIMINATOR: &[u8; 8] = b"testlist";
+
+    fn items(list: &List, data: &[u8]) -> Vec<u64> {
+        (0..list.len())
+            .map(|index| list.get(data, index, "test").unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn push_set_remove() {
+        let mut data = vec![0u8; List::bytes_for(8, 3) as usize];
+        let mut list =
+            List::new(DISCRIMINATOR, 8, Pubkey::new_unique(), &mut data, "test").unwrap();
+        assert_eq!(&data[..8], DISCRIMINATOR);
+        for item in 1..=3u64 {
+            list.push(&mut data, item, "test").unwrap();
+        }
+        assert_eq!(
+            list.push(&mut data, 4u64, "test"),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        list.set(&mut data, 1, 20u64, "test").unwrap();
+        assert_eq!(items(&list, &data), vec![1, 20, 3]);
+        list.remove(&mut data, 0, "test").unwrap();
+        assert_eq!(items(&list, &data), vec![3, 20]);
+        list.remove(&mut data, 1, "test").unwrap();
+        assert_eq!(items(&list, &data), vec![3]);
+        assert!(list.remove(&mut data, 1, "test").is_err());
+    }
+
+    #[test]
+    fn chunked_account_change() {
+        let mut data = vec![0u8; List::bytes_for(8, 5) as usize];
+        let mut list =
+            List::new(DISCRIMINATOR, 8, Pubkey::new_unique(), &mut data, "test").unwrap();
+        for item in 0..5u64 {
+            list.push(&mut data, item, "test").unwrap();
+        }
+        let new_account = Pubkey::new_unique();
+        let mut new_data = vec![0u8; List::bytes_for(8, 4) as usize];
+        assert_eq!(
+            list.start_changing_account(DISCRIMINATOR, new_account, &mut new_data, "test"),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        let mut new_data = vec![0u8; List::bytes_for(8, 10) as usize];
+        list.start_changing_account(DISCRIMINATOR, new_account, &mut new_data, "test")
+            .unwrap();
+        assert!(list.push(&mut data, 5u64, "test").is_err());
+        assert!(!list
+            .copy_next_chunk(&data, &mut new_data, 2, "test")
+            .unwrap());
+        assert!(!list
+            .copy_next_chunk(&data, &mut new_data, 2, "test")
+            .unwrap());
+        assert!(list
+            .copy_next_chunk(&data, &mut new_data, 2, "test")
+            .unwrap());
+        assert_eq!(list.account, new_account);
+        assert!(!list.is_changing_account());
+        assert_eq!(items(&list, &new_data), vec![0, 1, 2, 3, 4]);
+        list.push(&mut new_data, 5u64, "test").unwrap();
+        assert_eq!(list.capacity(new_data.len()).unwrap(), 10);
+    }
+
+    #[test]
+    fn rejects_short_data() {
+        let list = List {
+            item_size: 8,
+            count: 3,
+            ..List::default()
+        };
+        let mut data = vec![0u8; List::bytes_for(8, 2) as usize];
+        assert_eq!(
+            list.get::<u64>(&data, 0, "test"),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            list.set(&mut data, 0, 1u64, "test"),
+            Err(ProgramError::InvalidAccountData)
+        );
+        let mut removed = list.clone();
+        assert_eq!(
+            removed.remove(&mut data, 0, "test"),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(removed.len(), 3);
+
+        let mut changing = List {
+            new_account: Pubkey::new_unique(),
+            ..list
+        };
+        let mut new_data = vec![0u8; List::bytes_for(8, 3) as usize];
+        assert_eq!(
+            changing.copy_next_chunk(&data, &mut new_data, 3, "test"),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(changing.copied_count, 0);
+
+        let mut corrupt = List {
+            copied_count: 4,
+            ..changing
+        };
+        let data = vec![0u8; List::bytes_for(8, 3) as usize];
+        assert_eq!(
+            corrupt.copy_next_chunk(&data, &mut new_data, 3, "test"),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(corrupt.copied_count, 4);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let list = List {
+            account: Pubkey::new_unique(),
+            item_size: 8,
+            count: 3,
+            new_account: Pubkey::new_unique(),
+            copied_count: 2,
+        };
+        let json = serde_json::to_value(&list).unwrap();
+        assert_eq!(json["account"], list.account.to_string());
+        assert_eq!(json["new_account"], list.new_account.to_string());
+        assert_eq!(json["count"], 3);
+        let parsed: List = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.try_to_vec().unwrap(), list.try_to_vec().unwrap());
+    }
+}
@@ -1,39 +1,51 @@
-use borsh::{BorshDeserialize, BorshSerialize};
+use std::fmt::Display;
+
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction, msg,
-    program_error::ProgramError, program_pack::Pack, pubkey::Pubkey,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    msg,
+    program_error::ProgramError,
+    pubkey::{Pubkey, PUBKEY_BYTES},
 };
 
 use crate::{
-    calc::{shares_from_value, value_from_shares},
+    calc::{shares_from_value, shares_from_value_ceil, value_from_shares, MsolPrice},
     checks::check_address,
-    error::CommonError,
+    error::{CommonError, CAP_REACHED_ERROR_CODE},
     instructions::config_lp::{ConfigLpAccounts, ConfigLpData},
     located::Located,
     state::{
         fee::Fee, liq_pool::LiqPool, stake_system::StakeSystem, validator_system::ValidatorSystem,
     },
+    token::{is_token_program, unpack_token_account_data},
     ID,
 };
 use micro_anchor::{AccountDeserialize, Discriminator, InstructionBuilder, Owner};
-use std::mem::MaybeUninit;
 
-#[derive(Debug, BorshSerialize, BorshDeserialize, Clone)]
+#[derive(Debug, Default, BorshSerialize, BorshDeserialize, BorshSchema, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Marinade {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub msol_mint: Pubkey,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub admin_authority: Pubkey,
 
     // Target for withdrawing rent reserve SOLs. Save bot wallet account here
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub operational_sol_account: Pubkey,
     // treasury - external accounts managed by marinade DAO
     // pub treasury_sol_account: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub treasury_msol_account: Pubkey,
 
     // Bump seeds:
     pub reserve_bump_seed: u8,
     pub msol_mint_authority_bump_seed: u8,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub rent_exempt_for_token_acc: u64, // Token-Account For rent exempt
 
     // fee applied on rewards
@@ -48,20 +60,30 @@ pub struct Marinade {
     // epoch_stake_orders: u64,
     // epoch_unstake_orders: u64,
     pub liq_pool: LiqPool,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub available_reserve_balance: u64, // reserve_pda.lamports() - self.rent_exempt_for_token_acc. Virtual value (real may be > because of transfers into reserve). Use Update* to align
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub msol_supply: u64, // Virtual value (may be < because of token burn). Use Update* to align
     // For FE. Don't use it for token amount calculation
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub msol_price: u64,
 
     ///count tickets for delayed-unstake
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub circulating_ticket_count: u64,
     ///total lamports amount of generated and not claimed yet tickets
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub circulating_ticket_balance: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub lent_from_reserve: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub min_deposit: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub min_withdraw: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub staking_sol_cap: u64,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub emergency_cooling_down: u64,
 }
 
@@ -75,12 +97,19 @@ impl Marinade {
     pub const STAKE_LIST_SEED: &'static str = "stake_list";
     pub const VALIDATOR_LIST_SEED: &'static str = "validator_list";
 
-    pub fn serialized_len() -> usize {
-        unsafe { MaybeUninit::<Self>::zeroed().assume_init() }
-            .try_to_vec()
-            .unwrap()
-            .len()
-            + 8
+    pub const SERIALIZED_LEN: usize = 4 * PUBKEY_BYTES // msol_mint .. treasury_msol_account
+        + 1 // reserve_bump_seed
+        + 1 // msol_mint_authority_bump_seed
+        + 8 // rent_exempt_for_token_acc
+        + Fee::SERIALIZED_LEN // reward_fee
+        + StakeSystem::SERIALIZED_LEN
+        + ValidatorSystem::SERIALIZED_LEN
+        + LiqPool::SERIALIZED_LEN
+        + 10 * 8; // available_reserve_balance .. emergency_cooling_down
+
+    /// Account data length including the discriminator
+    pub const fn serialized_len() -> usize {
+        Self::SERIALIZED_LEN + 8
     }
 
     pub fn find_msol_mint_authority(state: &Pubkey) -> (Pubkey, u8) {
@@ -113,6 +142,7 @@ impl Marinade {
             &self.operational_sol_account,
             "operational_sol_account",
         )
+        .map_err(Into::into)
     }
 
     /*
@@ -131,7 +161,7 @@ impl Marinade {
             "treasury_msol_account",
         )?;
 
-        if treasury_msol_account.owner != &spl_token::ID {
+        if !is_token_program(treasury_msol_account.owner) {
             msg!(
                 "treasury_msol_account {} is not a token account",
                 treasury_msol_account.key
@@ -139,7 +169,10 @@ impl Marinade {
             return Ok(false); // Not an error. Admins may decide to reject fee transfers to themselves
         }
 
-        match spl_token::state::Account::unpack(treasury_msol_account.data.borrow().as_ref()) {
+        match unpack_token_account_data(
+            treasury_msol_account.owner,
+            treasury_msol_account.data.borrow().as_ref(),
+        ) {
             Ok(token_account) => {
                 if token_account.mint == self.msol_mint {
                     Ok(true)
@@ -165,7 +198,7 @@ impl Marinade {
     }
 
     pub fn check_msol_mint(&mut self, msol_mint: &Pubkey) -> ProgramResult {
-        check_address(msol_mint, &self.msol_mint, "msol_mint")
+        check_address(msol_mint, &self.msol_mint, "msol_mint").map_err(Into::into)
     }
 
     pub fn total_cooling_down(&self) -> u64 {
@@ -199,7 +232,7 @@ impl Marinade {
                 result_amount,
                 self.staking_sol_cap
             );
-            return Err(ProgramError::Custom(3782));
+            return Err(ProgramError::Custom(CAP_REACHED_ERROR_CODE));
         }
         Ok(())
     }
@@ -210,6 +243,52 @@ impl Marinade {
             .saturating_sub(self.circulating_ticket_balance) //tickets created -> cooling down lamports or lamports already in reserve and not claimed yet
     }
 
+    /// mSOL minted to the treasury for `rewards` at the current price (before the rewards are counted)
+    pub fn reward_fee_msol(&self, rewards: u64) -> Result<u64, CommonError> {
+        self.calc_msol_from_lamports(self.reward_fee.apply(rewards))
+    }
+
+    /// What update of a stake account grown from `last_update_delegated_lamports`
+    /// to `delegated_lamports` mints to the treasury and how it moves the price.
+    /// The fee is minted at the price before the rewards are counted, the same as the program does
+    pub fn reward_accrual(
+        &self,
+        last_update_delegated_lamports: u64,
+        delegated_lamports: u64,
+    ) -> Result<RewardAccrual, CommonError> {
+        let rewards = delegated_lamports.saturating_sub(last_update_delegated_lamports);
+        let slashed = last_update_delegated_lamports.saturating_sub(delegated_lamports);
+        let fee_lamports = self.reward_fee.apply(rewards);
+        let treasury_msol = self.calc_msol_from_lamports(fee_lamports)?;
+        let total_virtual_staked_lamports = self
+            .total_virtual_staked_lamports()
+            .checked_add(rewards)
+            .ok_or(CommonError::CalculationFailure)?
+            .saturating_sub(slashed);
+        let msol_supply = self
+            .msol_supply
+            .checked_add(treasury_msol)
+            .ok_or(CommonError::CalculationFailure)?;
+        Ok(RewardAccrual {
+            rewards,
+            slashed,
+            fee_lamports,
+            treasury_msol,
+            total_virtual_staked_lamports,
+            msol_supply,
+            msol_price: value_from_shares(
+                Self::PRICE_DENOMINATOR,
+                total_virtual_staked_lamports,
+                msol_supply,
+            )?,
+        })
+    }
+
+    /// exact msol price to convert with explicit rounding
+    pub fn exact_msol_price(&self) -> MsolPrice {
+        MsolPrice::new(self.total_virtual_staked_lamports(), self.msol_supply)
+    }
+
     /// calculate the amount of msol tokens corresponding to certain lamport amount
     pub fn calc_msol_from_lamports(&self, stake_lamports: u64) -> Result<u64, CommonError> {
         shares_from_value(
@@ -228,6 +307,145 @@ impl Marinade {
         )
     }
 
+    /// Result of liquid unstake of `msol_amount` the same way the program computes it.
+    /// `sol_leg_balance` is the liq pool SOL leg lamports minus rent_exempt_for_token_acc
+    pub fn liquid_unstake_quote(
+        &self,
+        msol_amount: u64,
+        sol_leg_balance: u64,
+    ) -> Result<LiquidUnstakeQuote, CommonError> {
+        // fee is computed based on the liquidity *after* the user takes the sol
+        let user_remove_lamports = self.calc_lamports_from_msol_amount(msol_amount)?;
+        let fee = if user_remove_lamports >= sol_leg_balance {
+            // user is removing all liquidity
+            self.liq_pool.lp_max_fee
+        } else {
+            self.liq_pool
+                .linear_fee(sol_leg_balance - user_remove_lamports)
+        };
+        let msol_fee = fee.apply(msol_amount);
+        let lamports_out = self.calc_lamports_from_msol_amount(msol_amount - msol_fee)?;
+        if lamports_out > sol_leg_balance {
+            return Err(CommonError::InsufficientLiquidity);
+        }
+        Ok(LiquidUnstakeQuote {
+            msol_amount,
+            fee,
+            msol_fee,
+            treasury_msol_cut: self.liq_pool.treasury_cut.apply(msol_fee),
+            lamports_out,
+        })
+    }
+
+    /// Minimal mSOL amount to liquid unstake to receive at least `lamports`
+    pub fn liquid_unstake_quote_for_lamports(
+        &self,
+        lamports: u64,
+        sol_leg_balance: u64,
+    ) -> Result<LiquidUnstakeQuote, CommonError> {
+        if lamports > sol_leg_balance {
+            return Err(CommonError::InsufficientLiquidity);
+        }
+        // minimal mSOL after fee which is worth the lamports
+        let net_msol = shares_from_value_ceil(
+            lamports,
+            self.total_virtual_staked_lamports(),
+            self.msol_supply,
+        )?;
+        // The fee only grows with the amount, so no amount below the one required by
+        // the fee of a lower amount can be enough. Iterate until the fee stops growing
+        let mut msol_amount = net_msol;
+        loop {
+            let quote = self.liquid_unstake_quote(msol_amount, sol_leg_balance)?;
+            let required = Self::msol_amount_before_fee(net_msol, quote.fee)?;
+            if required <= msol_amount {
+                return Ok(quote);
+            }
+            msol_amount = required;
+        }
+    }
+
+    /// minimal amount which is still >= `net_msol` after `fee` is taken
+    fn msol_amount_before_fee(net_msol: u64, fee: Fee) -> Result<u64, CommonError> {
+        if net_msol == 0 {
+            return Ok(0);
+        }
+        let keep_basis_points = 10_000u128
+            .checked_sub(fee.basis_points as u128)
+            .filter(|keep| *keep > 0)
+            .ok_or(CommonError::CalculationFailure)?;
+        // amount - floor(amount * fee) >= net  <=>  amount > (net - 1) / (1 - fee)
+        u64::try_from((net_msol as u128 - 1) * 10_000 / keep_basis_points + 1)
+            .map_err(|_| CommonError::CalculationFailure)
+    }
+
+    /// Bounds of user operations the program would accept now.
+    /// `sol_leg_balance` is the liq pool SOL leg lamports minus rent_exempt_for_token_acc,
+    /// `msol_leg_balance` is the liq pool mSOL leg token amount
+    pub fn limits(
+        &self,
+        sol_leg_balance: u64,
+        msol_leg_balance: u64,
+    ) -> Result<Limits, CommonError> {
+        let max_deposit = self
+            .staking_sol_cap
+            .saturating_sub(self.total_lamports_under_control());
+        let max_liquid_unstake_quote = self.max_liquid_unstake_quote(sol_leg_balance)?;
+        // add_liquidity checks the cap against the whole pool value
+        let liq_pool_value = sol_leg_balance
+            .checked_add(self.calc_lamports_from_msol_amount(msol_leg_balance)?)
+            .ok_or(CommonError::CalculationFailure)?;
+        Ok(Limits {
+            min_deposit: Limit::new(self.min_deposit, LimitReason::MinDeposit),
+            max_deposit: Limit::new(max_deposit, LimitReason::StakingCap),
+            min_withdraw: Limit::new(self.min_withdraw, LimitReason::MinWithdraw),
+            min_withdraw_msol: shares_from_value_ceil(
+                self.min_withdraw,
+                self.total_virtual_staked_lamports(),
+                self.msol_supply,
+            )?,
+            max_liquid_unstake: Limit::new(
+                max_liquid_unstake_quote.msol_amount,
+                LimitReason::PoolLiquidity,
+            ),
+            max_liquid_unstake_quote,
+            max_add_liquidity: Limit::new(
+                self.liq_pool
+                    .liquidity_sol_cap
+                    .saturating_sub(liq_pool_value),
+                LimitReason::LiquidityCap,
+            ),
+        })
+    }
+
+    /// Quote of the biggest mSOL amount liquid_unstake accepts
+    fn max_liquid_unstake_quote(
+        &self,
+        sol_leg_balance: u64,
+    ) -> Result<LiquidUnstakeQuote, CommonError> {
+        // Amounts worth less than the leg always pass. Above that the max fee applies
+        // and lamports_out only grows with the amount, so the accepted amounts are 0..=max.
+        // Overflowing amounts fail as well
+        let mut accepted = self.liquid_unstake_quote(0, sol_leg_balance)?;
+        let mut rejected = u64::MAX;
+        while rejected - accepted.msol_amount > 1 {
+            let msol_amount = accepted.msol_amount + (rejected - accepted.msol_amount) / 2;
+            match self.liquid_unstake_quote(msol_amount, sol_leg_balance) {
+                Ok(quote) => accepted = quote,
+                Err(_) => rejected = msol_amount,
+            }
+        }
+        Ok(accepted)
+    }
+
+    /// stake_delta not counting emergency_cooling_down:
+    /// reserve_balance - rent_exempt_for_token_acc + delayed_unstake_cooling_down - circulating_ticket_balance
+    pub fn stake_delta_without_emergency(&self, reserve_balance: u64) -> i128 {
+        reserve_balance.saturating_sub(self.rent_exempt_for_token_acc) as i128
+            + self.stake_system.delayed_unstake_cooling_down as i128
+            - self.circulating_ticket_balance as i128
+    }
+
     // **i128**: when do staking/unstaking use real reserve balance instead of virtual field
     pub fn stake_delta(&self, reserve_balance: u64) -> i128 {
         // Never try to stake lamports from emergency_cooling_down
@@ -237,9 +455,7 @@ impl Marinade {
         // preventing unstake duplication by recalculating stake-delta for negative values
 
         // OK. Lets get stake_delta without emergency first
-        let raw = reserve_balance.saturating_sub(self.rent_exempt_for_token_acc) as i128
-            + self.stake_system.delayed_unstake_cooling_down as i128
-            - self.circulating_ticket_balance as i128;
+        let raw = self.stake_delta_without_emergency(reserve_balance);
         if raw >= 0 {
             // When it >= 0 it is right value to use
             raw
@@ -282,6 +498,106 @@ impl Marinade {
     }
 }
 
+/// Result of recognizing the balance change of a stake account (update_active / update_deactivated)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RewardAccrual {
+    pub rewards: u64,
+    /// balance decrease. No fee is taken
+    pub slashed: u64,
+    /// reward_fee part of the rewards
+    pub fee_lamports: u64,
+    /// mSOL minted to treasury_msol_account for fee_lamports
+    pub treasury_msol: u64,
+    pub total_virtual_staked_lamports: u64,
+    pub msol_supply: u64,
+    /// new `Marinade::msol_price`
+    pub msol_price: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiquidUnstakeQuote {
+    pub msol_amount: u64,
+    pub fee: Fee,
+    /// part of msol_amount kept by the liq pool and the treasury
+    pub msol_fee: u64,
+    /// part of msol_fee going to treasury_msol_account
+    pub treasury_msol_cut: u64,
+    pub lamports_out: u64,
+}
+
+/// Why an operation amount is bounded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitReason {
+    /// `min_deposit`
+    MinDeposit,
+    /// `staking_sol_cap` minus `total_lamports_under_control`
+    StakingCap,
+    /// `min_withdraw` lamports value of the unstaked mSOL
+    MinWithdraw,
+    /// liq pool SOL leg balance
+    PoolLiquidity,
+    /// `liq_pool.liquidity_sol_cap` minus the pool value
+    /// (SOL leg balance plus the mSOL leg valued in lamports)
+    LiquidityCap,
+}
+
+impl Display for LimitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitReason::MinDeposit => write!(f, "minimal deposit"),
+            LimitReason::StakingCap => write!(f, "staking cap"),
+            LimitReason::MinWithdraw => write!(f, "minimal withdraw"),
+            LimitReason::PoolLiquidity => write!(f, "liquidity pool SOL balance"),
+            LimitReason::LiquidityCap => write!(f, "liquidity cap"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    pub amount: u64,
+    pub reason: LimitReason,
+}
+
+impl Limit {
+    pub fn new(amount: u64, reason: LimitReason) -> Self {
+        Self { amount, reason }
+    }
+}
+
+/// Amounts are lamports unless noted otherwise
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub min_deposit: Limit,
+    pub max_deposit: Limit,
+    /// applies to order unstake
+    pub min_withdraw: Limit,
+    /// mSOL worth `min_withdraw`
+    pub min_withdraw_msol: u64,
+    /// in mSOL
+    pub max_liquid_unstake: Limit,
+    /// liquid unstake of `max_liquid_unstake` mSOL
+    pub max_liquid_unstake_quote: LiquidUnstakeQuote,
+    pub max_add_liquidity: Limit,
+}
+
+impl Limits {
+    pub fn can_deposit(&self) -> bool {
+        self.max_deposit.amount >= self.min_deposit.amount && self.max_deposit.amount > 0
+    }
+
+    /// Checks `lamports` the same way deposit does
+    pub fn check_deposit(&self, lamports: u64) -> Result<(), Limit> {
+        if lamports < self.min_deposit.amount {
+            Err(self.min_deposit)
+        } else if lamports > self.max_deposit.amount {
+            Err(self.max_deposit)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 pub trait MarinadeHelpers {
     fn msol_mint_authority(&self) -> Pubkey;
     fn with_msol_mint_authority_seeds<R, F: FnOnce(&[&[u8]]) -> R>(&self, f: F) -> R;
@@ -327,7 +643,7 @@ where
     }
 
     fn check_reserve_address(&self, reserve: &Pubkey) -> ProgramResult {
-        check_address(reserve, &self.reserve_address(), "reserve")
+        check_address(reserve, &self.reserve_address(), "reserve").map_err(Into::into)
     }
 
     fn check_msol_mint_authority(&self, msol_mint_authority: &Pubkey) -> ProgramResult {
@@ -336,6 +652,7 @@ where
             &self.msol_mint_authority(),
             "msol_mint_authority",
         )
+        .map_err(Into::into)
     }
 
     // Instructions
@@ -362,3 +679,172 @@ impl Owner for Marinade {
 }
 
 impl AccountDeserialize for Marinade {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::proportional;
+    use proptest::prelude::*;
+
+    fn marinade(
+        total_virtual_staked_lamports: u64,
+        msol_supply: u64,
+        lp_liquidity_target: u64,
+    ) -> Marinade {
+        Marinade {
+            available_reserve_balance: total_virtual_staked_lamports,
+            msol_supply,
+            liq_pool: LiqPool {
+                lp_liquidity_target,
+                lp_max_fee: Fee::from_basis_points(300),
+                lp_min_fee: Fee::from_basis_points(30),
+                treasury_cut: Fee::from_basis_points(2500),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reward_fee_accrual() {
+        // price 2 lamports per mSOL, 10% fee
+        let mut state = marinade(4_000, 2_000, 0);
+        state.reward_fee = Fee::from_basis_points(1_000);
+        let accrual = state.reward_accrual(1_000, 1_200).unwrap();
+        assert_eq!(accrual.rewards, 200);
+        assert_eq!(accrual.fee_lamports, 20);
+        assert_eq!(accrual.treasury_msol, 10);
+        assert_eq!(state.reward_fee_msol(200).unwrap(), 10);
+        assert_eq!(accrual.total_virtual_staked_lamports, 4_200);
+        assert_eq!(accrual.msol_supply, 2_010);
+        assert_eq!(
+            accrual.msol_price,
+            proportional(Marinade::PRICE_DENOMINATOR, 4_200, 2_010).unwrap()
+        );
+
+        let slashed = state.reward_accrual(1_000, 900).unwrap();
+        assert_eq!((slashed.slashed, slashed.treasury_msol), (100, 0));
+        assert_eq!(slashed.total_virtual_staked_lamports, 3_900);
+    }
+
+    #[test]
+    fn liquid_unstake_inverse_is_minimal() {
+        let state = marinade(1_300_000, 1_000_000, 500_000);
+        let sol_leg_balance = 700_000;
+        for lamports in (0..=sol_leg_balance).step_by(7_919) {
+            let brute_force = (0..)
+                .find(|msol| {
+                    matches!(
+                        state.liquid_unstake_quote(*msol, sol_leg_balance),
+                        Ok(quote) if quote.lamports_out >= lamports
+                    )
+                })
+                .unwrap();
+            let quote = state
+                .liquid_unstake_quote_for_lamports(lamports, sol_leg_balance)
+                .unwrap();
+            assert_eq!(quote.msol_amount, brute_force, "lamports {}", lamports);
+        }
+    }
+
+    #[test]
+    fn liquid_unstake_inverse_insufficient_liquidity() {
+        let state = marinade(1_300_000, 1_000_000, 500_000);
+        assert!(matches!(
+            state.liquid_unstake_quote_for_lamports(700_001, 700_000),
+            Err(CommonError::InsufficientLiquidity)
+        ));
+    }
+
+    #[test]
+    fn limits() {
+        let mut state = marinade(1_300_000, 1_000_000, 500_000);
+        state.min_deposit = 1_000;
+        state.min_withdraw = 1_300;
+        state.staking_sol_cap = 2_000_000;
+        state.liq_pool.liquidity_sol_cap = 1_000_000;
+        let limits = state.limits(700_000, 100_000).unwrap();
+        assert_eq!(limits.max_deposit.amount, 700_000);
+        assert_eq!(limits.min_withdraw_msol, 1_000);
+        // the mSOL leg is worth 130_000
+        assert_eq!(limits.max_add_liquidity.amount, 170_000);
+        let max_msol = limits.max_liquid_unstake.amount;
+        assert_eq!(
+            state.liquid_unstake_quote(max_msol, 700_000).unwrap(),
+            limits.max_liquid_unstake_quote
+        );
+        assert!(limits.max_liquid_unstake_quote.lamports_out <= 700_000);
+        assert!(matches!(
+            state.liquid_unstake_quote(max_msol + 1, 700_000),
+            Err(CommonError::InsufficientLiquidity)
+        ));
+        // taking the whole leg pays the max fee
+        assert_eq!(
+            limits.max_liquid_unstake_quote.fee,
+            Fee::from_basis_points(300)
+        );
+        assert_eq!(
+            limits.check_deposit(999).unwrap_err().reason,
+            LimitReason::MinDeposit
+        );
+        assert_eq!(
+            limits.check_deposit(700_001).unwrap_err().reason,
+            LimitReason::StakingCap
+        );
+
+        state.staking_sol_cap = 1_000_000;
+        let limits = state.limits(0, 0).unwrap();
+        assert!(!limits.can_deposit());
+        assert_eq!(limits.max_deposit.amount, 0);
+        assert_eq!(limits.max_liquid_unstake.amount, 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let mut state = marinade(u64::MAX - 1, 3, 0);
+        state.admin_authority = Pubkey::new_unique();
+        state.reward_fee = Fee::from_basis_points(250);
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["admin_authority"], state.admin_authority.to_string());
+        assert_eq!(json["msol_supply"], "3");
+        assert_eq!(json["reward_fee"]["percent"], "2.5%");
+        assert_eq!(json["liq_pool"]["lp_supply"], "0");
+
+        let parsed: Marinade = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.try_to_vec().unwrap(), state.try_to_vec().unwrap());
+        // plain numbers are accepted too
+        let parsed: Marinade = serde_json::from_str(
+            &serde_json::to_string(&state)
+                .unwrap()
+                .replace("\"msol_supply\":\"3\"", "\"msol_supply\":3"),
+        )
+        .unwrap();
+        assert_eq!(parsed.msol_supply, 3);
+    }
+
+    proptest! {
+        #[test]
+        fn liquid_unstake_inverse_matches_forward(
+            msol_supply in 1_000_000_000..1_000_000_000_000_000u64,
+            price_bp in 10_000..30_000u64,
+            lp_liquidity_target in 0..100_000_000_000_000u64,
+            sol_leg_balance in 0..100_000_000_000_000u64,
+            lamports_bp in 0..=10_000u64,
+        ) {
+            let state = marinade(msol_supply / 10_000 * price_bp, msol_supply, lp_liquidity_target);
+            let lamports = proportional(sol_leg_balance, lamports_bp, 10_000).unwrap();
+            match state.liquid_unstake_quote_for_lamports(lamports, sol_leg_balance) {
+                Ok(quote) => {
+                    prop_assert_eq!(quote, state.liquid_unstake_quote(quote.msol_amount, sol_leg_balance).unwrap());
+                    prop_assert!(quote.lamports_out >= lamports);
+                    if quote.msol_amount > 0 {
+                        let less = state.liquid_unstake_quote(quote.msol_amount - 1, sol_leg_balance);
+                        prop_assert!(!matches!(less, Ok(less) if less.lamports_out >= lamports));
+                    }
+                }
+                Err(err) => prop_assert!(matches!(err, CommonError::InsufficientLiquidity)),
+            }
+        }
+    }
+}
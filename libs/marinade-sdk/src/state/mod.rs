@@ -4,4 +4,5 @@ pub mod stake_system;
 pub mod validator_system;
 pub mod delayed_unstake_ticket;
 pub mod fee;
-pub mod list;
\ No newline at end of file
+pub mod list;
+pub mod layout;
\ No newline at end of file
@@ -4,16 +4,20 @@ use crate::{
     state::{list::List, marinade::Marinade},
     ID,
 };
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use solana_program::{
     account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
     pubkey::Pubkey,
 };
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize, BorshSchema)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StakeRecord {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub stake_account: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub last_update_delegated_lamports: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub last_update_epoch: u64,
     pub is_emergency_unstaking: u8, // 1 for cooling down after emergency unstake, 0 otherwise
 }
@@ -22,21 +26,26 @@ impl StakeRecord {
     pub const DISCRIMINATOR: &'static [u8; 8] = b"staker__";
 }
 
-#[derive(Clone, BorshSerialize, BorshDeserialize, Debug)]
+#[derive(Clone, Default, BorshSerialize, BorshDeserialize, BorshSchema, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StakeSystem {
     pub stake_list: List,
     //pub last_update_epoch: u64,
     //pub updated_during_last_epoch: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub delayed_unstake_cooling_down: u64,
     pub stake_deposit_bump_seed: u8,
     pub stake_withdraw_bump_seed: u8,
 
     /// set by admin, how much slots before the end of the epoch, stake-delta can start
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub slots_for_stake_delta: u64,
     /// Marks the start of stake-delta operations, meaning that if somebody starts a delayed-unstake ticket
     /// after this var is set with epoch_num the ticket will have epoch_created = current_epoch+1
     /// (the user must wait one more epoch, because their unstake-delta will be execute in this epoch)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub last_stake_delta_epoch: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub min_stake: u64, // Minimal stake account delegation
     /// can be set by validator-manager-auth to allow a second run of stake-delta to stake late stakers in the last minute of the epoch
     /// so we maximize user's rewards
@@ -47,6 +56,15 @@ impl StakeSystem {
     pub const STAKE_WITHDRAW_SEED: &'static [u8] = b"withdraw";
     pub const STAKE_DEPOSIT_SEED: &'static [u8] = b"deposit";
 
+    pub const SERIALIZED_LEN: usize = List::SERIALIZED_LEN // stake_list
+        + 8 // delayed_unstake_cooling_down
+        + 1 // stake_deposit_bump_seed
+        + 1 // stake_withdraw_bump_seed
+        + 8 // slots_for_stake_delta
+        + 8 // last_stake_delta_epoch
+        + 8 // min_stake
+        + 4; // extra_stake_delta_runs
+
     pub fn bytes_for_list(count: u32, additional_record_space: u32) -> u32 {
         List::bytes_for(
             StakeRecord::default().try_to_vec().unwrap().len() as u32 + additional_record_space,
@@ -126,6 +144,7 @@ where
             &self.stake_withdraw_authority(),
             "stake_withdraw_authority",
         )
+        .map_err(Into::into)
     }
 
     fn stake_deposit_authority(&self) -> Pubkey {
@@ -148,5 +167,26 @@ where
             &self.stake_deposit_authority(),
             "stake_deposit_authority",
         )
+        .map_err(Into::into)
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stake_record_json_round_trip() {
+        let record = StakeRecord {
+            stake_account: Pubkey::new_unique(),
+            last_update_delegated_lamports: u64::MAX,
+            last_update_epoch: 300,
+            is_emergency_unstaking: 1,
+        };
+        let json = serde_json::to_value(record).unwrap();
+        assert_eq!(json["stake_account"], record.stake_account.to_string());
+        assert_eq!(json["last_update_delegated_lamports"], u64::MAX.to_string());
+        assert_eq!(json["is_emergency_unstaking"], 1);
+        assert_eq!(serde_json::from_value::<StakeRecord>(json).unwrap(), record);
     }
 }
@@ -1,20 +1,27 @@
 //use std::convert::TryInto;
 
 use crate::{calc::proportional, checks::check_address, error::CommonError, state::list::List, ID};
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
-    pubkey::Pubkey,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::{Pubkey, PUBKEY_BYTES},
 };
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize, BorshSchema)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValidatorRecord {
     /// Validator vote pubkey
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub validator_account: Pubkey,
 
     /// Validator total balance in lamports
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub active_balance: u64, // must be 0 for removing
     pub score: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub last_stake_delta_epoch: u64,
     pub duplication_flag_bump_seed: u8,
 }
@@ -78,18 +85,27 @@ impl ValidatorRecord {
     }
 }
 
-#[derive(Clone, BorshSerialize, BorshDeserialize, Debug)]
+#[derive(Clone, Default, BorshSerialize, BorshDeserialize, BorshSchema, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValidatorSystem {
     pub validator_list: List,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey"))]
     pub manager_authority: Pubkey,
     pub total_validator_score: u32,
     /// sum of all active lamports staked
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::u64_string"))]
     pub total_active_balance: u64,
     /// allow & auto-add validator when a user deposits a stake-account of a non-listed validator
     pub auto_add_validator_enabled: u8,
 }
 
 impl ValidatorSystem {
+    pub const SERIALIZED_LEN: usize = List::SERIALIZED_LEN // validator_list
+        + PUBKEY_BYTES // manager_authority
+        + 4 // total_validator_score
+        + 8 // total_active_balance
+        + 1; // auto_add_validator_enabled
+
     pub fn bytes_for_list(count: u32, additional_record_space: u32) -> u32 {
         List::bytes_for(
             ValidatorRecord::default().try_to_vec().unwrap().len() as u32 + additional_record_space,
@@ -159,5 +175,33 @@ impl ValidatorSystem {
             &self.manager_authority,
             "validator_manager_authority",
         )
+        .map_err(Into::into)
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validator_record_json_round_trip() {
+        let record = ValidatorRecord {
+            validator_account: Pubkey::new_unique(),
+            active_balance: u64::MAX,
+            score: 7,
+            last_stake_delta_epoch: 300,
+            duplication_flag_bump_seed: 255,
+        };
+        let json = serde_json::to_value(record).unwrap();
+        assert_eq!(
+            json["validator_account"],
+            record.validator_account.to_string()
+        );
+        assert_eq!(json["active_balance"], u64::MAX.to_string());
+        assert_eq!(json["score"], 7);
+        assert_eq!(
+            serde_json::from_value::<ValidatorRecord>(json).unwrap(),
+            record
+        );
     }
 }
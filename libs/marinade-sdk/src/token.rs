@@ -0,0 +1,238 @@
+//! Token account and mint parsing accepting both spl-token and Token-2022
+//!
+//! Token-2022 keeps the spl-token base layouts and appends an account type byte
+//! followed by TLV extensions, so the base state is parsed the same way for both programs.
+
+use solana_program::{
+    account_info::AccountInfo,
+    msg,
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+};
+use spl_token::state::{Account as TokenAccount, Mint, Multisig};
+
+use crate::checks::CheckError;
+
+/// The Token-2022 program ID
+pub static TOKEN_2022_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
+    6, 221, 246, 225, 238, 117, 143, 222, 24, 66, 93, 188, 228, 108, 205, 218, 182, 26, 252, 77,
+    131, 185, 13, 39, 254, 189, 249, 40, 216, 161, 139, 252,
+]); // "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb"
+
+/// Token-2022 account type stored right after the base account layout
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == spl_token::ID || *program_id == TOKEN_2022_PROGRAM_ID
+}
+
+pub fn unpack_token_account(
+    account: &AccountInfo,
+    field_name: &str,
+) -> Result<TokenAccount, ProgramError> {
+    check_token_program(account, field_name)?;
+    let result = unpack_token_account_data(account.owner, account.data.borrow().as_ref());
+    if let Err(e) = &result {
+        msg!(
+            "{} {} can not be parsed as token account ({})",
+            field_name,
+            account.key,
+            e
+        );
+    }
+    result
+}
+
+pub fn unpack_mint(account: &AccountInfo, field_name: &str) -> Result<Mint, ProgramError> {
+    check_token_program(account, field_name)?;
+    let result = unpack_mint_data(account.owner, account.data.borrow().as_ref());
+    if let Err(e) = &result {
+        msg!(
+            "{} {} can not be parsed as mint ({})",
+            field_name,
+            account.key,
+            e
+        );
+    }
+    result
+}
+
+pub fn check_token_program(account: &AccountInfo, field_name: &str) -> Result<(), CheckError> {
+    if is_token_program(account.owner) {
+        Ok(())
+    } else {
+        Err(CheckError::TokenProgram {
+            field_name: field_name.to_string(),
+            actual: *account.owner,
+        })
+    }
+}
+
+/// Parses the base state of a token account owned by `owner_program`
+pub fn unpack_token_account_data(
+    owner_program: &Pubkey,
+    data: &[u8],
+) -> Result<TokenAccount, ProgramError> {
+    unpack_base(
+        owner_program,
+        data,
+        TokenAccount::LEN,
+        TokenAccount::LEN,
+        ACCOUNT_TYPE_ACCOUNT,
+    )
+}
+
+/// Parses the base state of a mint owned by `owner_program`
+pub fn unpack_mint_data(owner_program: &Pubkey, data: &[u8]) -> Result<Mint, ProgramError> {
+    unpack_base(
+        owner_program,
+        data,
+        Mint::LEN,
+        TokenAccount::LEN,
+        ACCOUNT_TYPE_MINT,
+    )
+}
+
+/// Extended Token-2022 accounts are `base | zero padding up to account_type_offset | account type | extensions`
+fn unpack_base<T: Pack + IsInitialized>(
+    owner_program: &Pubkey,
+    data: &[u8],
+    base_len: usize,
+    account_type_offset: usize,
+    account_type: u8,
+) -> Result<T, ProgramError> {
+    if !is_token_program(owner_program) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *owner_program == spl_token::ID || data.len() == base_len {
+        return T::unpack(data);
+    }
+    if data.len() <= account_type_offset
+        || data.len() == Multisig::LEN
+        || data[account_type_offset] != account_type
+        || data[base_len..account_type_offset].iter().any(|b| *b != 0)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    T::unpack(&data[..base_len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::program_option::COption;
+    use spl_token::state::AccountState;
+
+    fn mint_data(len: usize) -> Vec<u8> {
+        let mint = Mint {
+            mint_authority: COption::Some(Pubkey::new_unique()),
+            supply: 5,
+            decimals: 9,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        let mut data = vec![0u8; len];
+        mint.pack_into_slice(&mut data[..Mint::LEN]);
+        if len > TokenAccount::LEN {
+            data[TokenAccount::LEN] = ACCOUNT_TYPE_MINT;
+        }
+        data
+    }
+
+    fn token_account_data(len: usize) -> Vec<u8> {
+        let account = TokenAccount {
+            mint: Pubkey::new_unique(),
+            owner: Pubkey::new_unique(),
+            amount: 7,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        };
+        let mut data = vec![0u8; len];
+        account.pack_into_slice(&mut data[..TokenAccount::LEN]);
+        if len > TokenAccount::LEN {
+            data[TokenAccount::LEN] = ACCOUNT_TYPE_ACCOUNT;
+        }
+        data
+    }
+
+    #[test]
+    fn rejects_other_owners() {
+        let other = Pubkey::new_unique();
+        assert_eq!(
+            unpack_mint_data(&other, &mint_data(Mint::LEN)).unwrap_err(),
+            ProgramError::IncorrectProgramId
+        );
+        assert_eq!(
+            unpack_token_account_data(&other, &token_account_data(TokenAccount::LEN)).unwrap_err(),
+            ProgramError::IncorrectProgramId
+        );
+    }
+
+    #[test]
+    fn base_layouts() {
+        for program in [spl_token::ID, TOKEN_2022_PROGRAM_ID] {
+            assert_eq!(
+                unpack_mint_data(&program, &mint_data(Mint::LEN))
+                    .unwrap()
+                    .supply,
+                5
+            );
+            assert_eq!(
+                unpack_token_account_data(&program, &token_account_data(TokenAccount::LEN))
+                    .unwrap()
+                    .amount,
+                7
+            );
+        }
+        // spl-token has no extensions
+        assert!(unpack_mint_data(&spl_token::ID, &mint_data(TokenAccount::LEN + 10)).is_err());
+    }
+
+    #[test]
+    fn token_2022_extensions() {
+        let program = TOKEN_2022_PROGRAM_ID;
+        let mut mint = mint_data(TokenAccount::LEN + 10);
+        assert_eq!(unpack_mint_data(&program, &mint).unwrap().supply, 5);
+        let mut account = token_account_data(TokenAccount::LEN + 10);
+        assert_eq!(
+            unpack_token_account_data(&program, &account)
+                .unwrap()
+                .amount,
+            7
+        );
+
+        // account type byte must match
+        assert_eq!(
+            unpack_token_account_data(&program, &mint).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+        account[TokenAccount::LEN] = ACCOUNT_TYPE_MINT;
+        assert_eq!(
+            unpack_token_account_data(&program, &account).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+
+        // mint padding up to the account type must be zero
+        mint[Mint::LEN] = 1;
+        assert_eq!(
+            unpack_mint_data(&program, &mint).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+
+        // no account type byte
+        assert_eq!(
+            unpack_mint_data(&program, &mint_data(TokenAccount::LEN)).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+
+        // a multisig is never an extended account
+        let mut multisig = token_account_data(Multisig::LEN);
+        multisig[TokenAccount::LEN] = ACCOUNT_TYPE_ACCOUNT;
+        assert_eq!(
+            unpack_token_account_data(&program, &multisig).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+}